@@ -2,12 +2,93 @@ import './style.css';
 import tileart from './tileart/highres/index.js';
 import { init, render_map } from './pkg';
 
+var seedInput = document.getElementById("seed");
+var errorText = document.getElementById("error");
+var options = {
+    missing_art: "placeholder",
+    generator: { weights: { preset: "balanced" }, density: { field: "none" } },
+    regenerate: [],
+};
+var lastReport = undefined;
+
+function render(seed) {
+    errorText.textContent = "";
+    render_map(tileart, seed, options).then((report) => {
+        lastReport = report;
+        seedInput.value = report.seed.toString();
+        if (report.failed_art.length > 0) {
+            errorText.textContent = "Some tile art failed to load: " + report.failed_art.join(", ");
+        }
+    }).catch((error) => {
+        errorText.textContent = "Failed to render map: " + error.message;
+    });
+}
+
 init();
-render_map(tileart);
+render(undefined);
 
 var refreshButton = document.getElementById("refresh");
 refreshButton.addEventListener("click", function() {
-    render_map(tileart);
+    options.regenerate = [];
+    render(undefined);
+});
+
+var loadSeedButton = document.getElementById("load-seed");
+loadSeedButton.addEventListener("click", function() {
+    options.regenerate = [];
+    render(BigInt(seedInput.value));
+});
+
+var presetSelect = document.getElementById("preset");
+presetSelect.addEventListener("change", function() {
+    options.generator.weights.preset = presetSelect.value;
+    options.regenerate = [];
+    render(undefined);
+});
+
+var densitySelect = document.getElementById("density");
+densitySelect.addEventListener("change", function() {
+    options.generator.density.field = densitySelect.value;
+    options.regenerate = [];
+    render(undefined);
+});
+
+// Drag across the map to lay the tiles under the selection again
+var canvas = document.getElementById("canvas");
+var dragStart = undefined;
+
+function tileAt(event) {
+    let rect = canvas.getBoundingClientRect();
+    let x = Math.floor((event.clientX - rect.left) / rect.width * lastReport.columns);
+    let y = Math.floor((event.clientY - rect.top) / rect.height * lastReport.rows);
+    return [x, y];
+}
+
+function randomSeed() {
+    let words = crypto.getRandomValues(new Uint32Array(2));
+    return (BigInt(words[0]) << 32n) | BigInt(words[1]);
+}
+
+canvas.addEventListener("mousedown", function(event) {
+    if (lastReport !== undefined) {
+        dragStart = tileAt(event);
+    }
+});
+
+canvas.addEventListener("mouseup", function(event) {
+    if (dragStart === undefined) {
+        return;
+    }
+    let [x, y] = tileAt(event);
+    let rect = {
+        x: Math.min(x, dragStart[0]),
+        y: Math.min(y, dragStart[1]),
+        width: Math.abs(x - dragStart[0]) + 1,
+        height: Math.abs(y - dragStart[1]) + 1,
+    };
+    dragStart = undefined;
+    options.regenerate.push({ cells: { rect: rect }, seed: randomSeed() });
+    render(lastReport.seed);
 });
 
 var downloadButton = document.getElementById("download");
@@ -0,0 +1,554 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use log::{info, warn};
+
+use carcassonne_map_generator::catalog::{Catalog, MissingArtPolicy, CATALOG_FILE_NAME};
+use carcassonne_map_generator::constraints::{Constraints, DEFAULT_REPAIR_ROUNDS};
+use carcassonne_map_generator::density::{
+    Density, DensityField, DEFAULT_DENSITY_SCALE, DEFAULT_DENSITY_STRENGTH,
+};
+use carcassonne_map_generator::features;
+use carcassonne_map_generator::generator::{
+    self, Algorithm, FlowDirection, GeneratorConfig, Pin, RiverConfig, RiverMouth, Sampling,
+    Selection, DEFAULT_MEANDER,
+};
+use carcassonne_map_generator::layout::{
+    parse_pair, Layout, Orientation, OutputSize, PaperSize, DEFAULT_DPI, DEFAULT_TILE_MM,
+    DEFAULT_TILE_SIZE,
+};
+use carcassonne_map_generator::map::{BorderPolicy, Map, Wrap};
+use carcassonne_map_generator::placeholder::{
+    CITY_COLOUR, CITY_DEPTH, CLOISTER_COLOUR, CLOISTER_SIZE, FIELD_COLOUR, RIVER_COLOUR,
+    RIVER_WIDTH, ROAD_COLOUR, ROAD_WIDTH,
+};
+use carcassonne_map_generator::rng::Rng;
+use carcassonne_map_generator::shape::Shape;
+use carcassonne_map_generator::tile::{Feature, TileSpec};
+use carcassonne_map_generator::weights::{Preset, Weights};
+
+/// Generates a Carcassonne map and writes it to a PNG file.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Seed for the generator, a random seed is used if not given
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Directory containing the tile art, with `base` and `river` subdirectories
+    #[arg(long, default_value = "tileart/highres")]
+    tileset: PathBuf,
+
+    /// Tile catalog to use, defaults to the one in the tileset directory
+    #[arg(long)]
+    catalog: Option<PathBuf>,
+
+    /// Paper size to fill, a0 to a5, letter, tabloid or WIDTHxHEIGHT in mm.
+    /// This is the default, using a4
+    #[arg(long, conflicts_with_all = ["grid", "width"])]
+    paper: Option<PaperSize>,
+
+    /// Printing resolution when filling a paper size
+    #[arg(long, default_value_t = DEFAULT_DPI)]
+    dpi: u32,
+
+    /// Paper orientation, portrait or landscape
+    #[arg(long, default_value = "portrait")]
+    orientation: Orientation,
+
+    /// Size of each tile in millimetres when filling a paper size
+    #[arg(long, default_value_t = DEFAULT_TILE_MM)]
+    tile_mm: f64,
+
+    /// Number of tiles to generate written as COLUMNSxROWS, instead of
+    /// filling a paper size
+    #[arg(long, value_parser = parse_grid, conflicts_with = "width")]
+    grid: Option<(u32, u32)>,
+
+    /// Width of the output image in pixels, instead of filling a paper size
+    #[arg(long, requires = "height")]
+    width: Option<u32>,
+
+    /// Height of the output image in pixels
+    #[arg(long, requires = "width")]
+    height: Option<u32>,
+
+    /// Size of each tile in pixels when using --grid or --width and --height
+    #[arg(long, default_value_t = DEFAULT_TILE_SIZE)]
+    tile_size: u32,
+
+    /// What to do when a tile's art fails to load: fail, skip or placeholder
+    #[arg(long, default_value = "fail")]
+    missing_art: MissingArtPolicy,
+
+    /// Algorithm used to fill the map, scanline or wfc, or organic to grow it
+    /// from a start tile as a game is played
+    #[arg(long, default_value = "scanline")]
+    algorithm: Algorithm,
+
+    /// How tiles are chosen: weighted by their count in the box, or faithful
+    /// to steer the map towards each tile's share of the box
+    #[arg(long, default_value = "weighted")]
+    sampling: Sampling,
+
+    /// Style of map, weighting tiles by their features: balanced,
+    /// dense-cities, countryside, road-network or monastic
+    #[arg(long, default_value = "balanced")]
+    preset: Preset,
+
+    /// Percentage weight of city edges, overriding the preset
+    #[arg(long)]
+    city_weight: Option<u32>,
+
+    /// Percentage weight of road edges, overriding the preset
+    #[arg(long)]
+    road_weight: Option<u32>,
+
+    /// Percentage weight of edges with nothing but field, overriding the
+    /// preset
+    #[arg(long)]
+    field_weight: Option<u32>,
+
+    /// Percentage weight of tiles with a cloister, overriding the preset
+    #[arg(long)]
+    cloister_weight: Option<u32>,
+
+    /// Percentage weight of a tile given by its id from the catalog, on top
+    /// of its features, 0 to never use it. May be repeated
+    #[arg(long = "tile-weight", value_name = "TILE=PERCENT", value_parser = parse_tile_weight)]
+    tile_weights: Vec<(String, u32)>,
+
+    /// Where the map varies from urban, favouring cities, to wild, favouring
+    /// fields and cloisters: none or noise
+    #[arg(long, default_value = "none")]
+    density: DensityField,
+
+    /// Grayscale PNG image stretched over the map in place of --density,
+    /// light pixels urban and dark ones wild
+    #[arg(long, conflicts_with = "density")]
+    density_image: Option<PathBuf>,
+
+    /// Tiles across each urban or wild region of --density noise
+    #[arg(long, default_value_t = DEFAULT_DENSITY_SCALE)]
+    density_scale: u32,
+
+    /// Percentage of how strongly the density field sways the tiles chosen
+    #[arg(long, default_value_t = DEFAULT_DENSITY_STRENGTH)]
+    density_strength: u32,
+
+    /// What may run off the edge of the map: open, closed or roads-only
+    #[arg(long, default_value = "open")]
+    border: BorderPolicy,
+
+    /// Join the edges of the map to the opposite edge so the image repeats
+    /// seamlessly: none, x, y or both
+    #[arg(long, default_value = "none")]
+    wrap: Wrap,
+
+    /// Where a river rising from a source ends: lake or border
+    #[arg(long, default_value = "lake")]
+    river_mouth: RiverMouth,
+
+    /// Fewest tiles in the river, counting its source and lake
+    #[arg(long)]
+    river_min_length: Option<u32>,
+
+    /// Most tiles in the river, counting its source and lake
+    #[arg(long)]
+    river_max_length: Option<u32>,
+
+    /// Direction the river flows across the map: any, north, east, south or
+    /// west
+    #[arg(long, default_value = "any")]
+    river_direction: FlowDirection,
+
+    /// Percentage chance the river turns aside at each tile
+    #[arg(long, default_value_t = DEFAULT_MEANDER, value_parser = clap::value_parser!(u32).range(0..=100))]
+    river_meander: u32,
+
+    /// Let the river pass beside an earlier stretch of itself
+    #[arg(long)]
+    river_self_adjacent: bool,
+
+    /// Number of rivers on the map
+    #[arg(long, default_value_t = 1)]
+    rivers: u32,
+
+    /// Number of times the rivers fork, as many as there are fork tiles in
+    /// one box if not given
+    #[arg(long)]
+    river_forks: Option<u32>,
+
+    /// Let a river flow into another one
+    #[arg(long)]
+    river_merge: bool,
+
+    /// Lay the map with the tiles from this many copies of the game, using
+    /// each tile at most once
+    #[arg(long)]
+    copies: Option<u32>,
+
+    /// Shrink the map to fit the tiles from --copies instead of failing
+    #[arg(long, requires = "copies")]
+    fit_to_deck: bool,
+
+    /// Shape of the map, cells outside it are left empty: rectangle, circle,
+    /// hexagon or island
+    #[arg(long, default_value = "rectangle")]
+    shape: Shape,
+
+    /// PNG image stretched over the map in place of --shape, laying the
+    /// cells whose centre falls on a light opaque pixel
+    #[arg(long, conflicts_with = "shape")]
+    mask: Option<PathBuf>,
+
+    /// Id of the tile an organic map grows from, the river's source or the
+    /// base game's start tile if not given
+    #[arg(long)]
+    start_tile: Option<String>,
+
+    /// Most tiles laid on an organic map, the whole deck or one box's worth
+    /// if not given
+    #[arg(long)]
+    tile_budget: Option<u32>,
+
+    /// Fewest tiles in any city
+    #[arg(long)]
+    min_city_size: Option<u32>,
+
+    /// Most tiles in any city
+    #[arg(long)]
+    max_city_size: Option<u32>,
+
+    /// Fewest cities closed off on every side
+    #[arg(long)]
+    completed_cities: Option<u32>,
+
+    /// Most tiles along any road
+    #[arg(long)]
+    max_road_length: Option<u32>,
+
+    /// Fewest cloisters on the map
+    #[arg(long)]
+    min_cloisters: Option<u32>,
+
+    /// Link every city to the others by road
+    #[arg(long)]
+    cities_on_roads: bool,
+
+    /// Times a small window of the map is laid again to meet the constraints
+    #[arg(long, default_value_t = DEFAULT_REPAIR_ROUNDS)]
+    repair_rounds: u32,
+
+    /// Place this tile before generating the rest of the map around it, given
+    /// as X,Y,TILE or X,Y,TILE,ROTATION with the tile's id from the catalog
+    /// and the number of quarter turns anticlockwise. May be repeated
+    #[arg(long = "pin", value_name = "X,Y,TILE[,ROTATION]")]
+    pins: Vec<Pin>,
+
+    /// After generating the map, clear this rectangle given as
+    /// X,Y,WIDTHxHEIGHT and lay it again around the tiles kept. May be
+    /// repeated
+    #[arg(long, value_name = "X,Y,WIDTHxHEIGHT")]
+    regenerate: Vec<Selection>,
+
+    /// Seed for laying the regenerated rectangles, a random seed is used if
+    /// not given
+    #[arg(long, requires = "regenerate")]
+    regenerate_seed: Option<u64>,
+
+    /// Also write the cities, roads, rivers, fields and cloisters on the map
+    /// to this JSON file
+    #[arg(long)]
+    features: Option<PathBuf>,
+
+    /// Also write each base game tile's target and actual share of the map
+    /// to this JSON file
+    #[arg(long)]
+    tile_stats: Option<PathBuf>,
+
+    /// Path to write the PNG to
+    #[arg(short, long, default_value = "map.png")]
+    output: PathBuf,
+}
+
+impl Args {
+    fn output_size(&self) -> OutputSize {
+        if let Some((columns, rows)) = self.grid {
+            return OutputSize::Grid {
+                columns,
+                rows,
+                tile_size: self.tile_size,
+            };
+        }
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            return OutputSize::Canvas {
+                width,
+                height,
+                tile_size: self.tile_size,
+            };
+        }
+        OutputSize::Paper {
+            paper: self.paper.unwrap_or(PaperSize::A4),
+            dpi: self.dpi,
+            orientation: self.orientation,
+            tile_mm: self.tile_mm,
+        }
+    }
+}
+
+fn parse_tile_weight(s: &str) -> Result<(String, u32), String> {
+    let expected = || format!("expected TILE=PERCENT, got {}", s);
+    let (tile, weight) = s.split_once('=').ok_or_else(expected)?;
+    let weight = weight.trim().parse().map_err(|_| expected())?;
+    Ok((tile.trim().to_string(), weight))
+}
+
+fn parse_grid(s: &str) -> Result<(u32, u32), String> {
+    parse_pair(s).ok_or_else(|| format!("expected COLUMNSxROWS, got {}", s))
+}
+
+/// Art for a tile in each of its four rotations.
+type RotatedArt = [RgbaImage; 4];
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    if let Err(err) = run(Args::parse()) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let seed = args.seed.unwrap_or_else(random_seed);
+    info!("Rendering map with seed {}...", seed);
+
+    let catalog_path = args
+        .catalog
+        .clone()
+        .unwrap_or_else(|| args.tileset.join(CATALOG_FILE_NAME));
+    let catalog = Catalog::from_json(&std::fs::read_to_string(&catalog_path)?)?;
+    let layout = args.output_size().layout()?.wrapped(args.wrap);
+    let (tiles, images) = load_tile_images(
+        catalog.tiles,
+        &args.tileset,
+        args.missing_art,
+        layout.tile_size,
+    )?;
+    info!("Finished loading tile art");
+
+    let config = GeneratorConfig {
+        algorithm: args.algorithm,
+        sampling: args.sampling,
+        copies: args.copies,
+        fit_to_deck: args.fit_to_deck,
+        border: args.border,
+        wrap: args.wrap,
+        river: RiverConfig {
+            mouth: args.river_mouth,
+            min_length: args.river_min_length,
+            max_length: args.river_max_length,
+            direction: args.river_direction,
+            meander: args.river_meander,
+            self_adjacent: args.river_self_adjacent,
+            count: args.rivers,
+            forks: args.river_forks,
+            merge: args.river_merge,
+        },
+        pins: args.pins.clone(),
+        shape: match &args.mask {
+            Some(path) => Shape::Png(std::fs::read(path)?),
+            None => args.shape.clone(),
+        },
+        weights: Weights {
+            preset: args.preset,
+            city: args.city_weight,
+            road: args.road_weight,
+            field: args.field_weight,
+            cloister: args.cloister_weight,
+            tiles: args.tile_weights.iter().cloned().collect(),
+        },
+        density: Density {
+            field: match &args.density_image {
+                Some(path) => DensityField::Png(std::fs::read(path)?),
+                None => args.density.clone(),
+            },
+            scale: args.density_scale,
+            strength: args.density_strength,
+        },
+        constraints: Constraints {
+            min_city_size: args.min_city_size,
+            max_city_size: args.max_city_size,
+            completed_cities: args.completed_cities,
+            max_road_length: args.max_road_length,
+            min_cloisters: args.min_cloisters,
+            cities_on_roads: args.cities_on_roads,
+            repair_rounds: args.repair_rounds,
+        },
+        start_tile: args.start_tile.clone(),
+        tile_budget: args.tile_budget,
+    };
+    let mut map = generator::generate_map(
+        tiles,
+        layout.columns,
+        layout.rows,
+        &config,
+        &mut Rng::new(seed),
+    )?;
+    let regenerate_seed = match args.regenerate.is_empty() {
+        true => None,
+        false => Some(args.regenerate_seed.unwrap_or_else(random_seed)),
+    };
+    if let Some(regenerate_seed) = regenerate_seed {
+        let mut rng = Rng::new(regenerate_seed);
+        for selection in &args.regenerate {
+            generator::regenerate(&mut map, selection, &config, &mut rng)?;
+        }
+    }
+
+    for unsatisfied in config.constraints.check(&map) {
+        warn!("Constraint not met: {}", unsatisfied);
+    }
+
+    let canvas = draw_map(&map, &images, &layout);
+    canvas.save(&args.output)?;
+    if let Some(path) = &args.features {
+        let graph = features::find_features(&map);
+        std::fs::write(path, serde_json::to_string(&graph)?)?;
+    }
+    if let Some(path) = &args.tile_stats {
+        let frequencies = generator::tile_frequencies(&map, &config);
+        for tile in &frequencies {
+            info!(
+                "{}: {:.1}% of the map, target {:.1}%",
+                tile.id, tile.actual, tile.target
+            );
+        }
+        std::fs::write(path, serde_json::to_string(&frequencies)?)?;
+    }
+    print!(
+        "Wrote {} ({}x{} tiles) with seed {}",
+        args.output.display(),
+        map.size_x(),
+        map.size_y(),
+        seed
+    );
+    match regenerate_seed {
+        Some(regenerate_seed) => println!(", regenerated with seed {}", regenerate_seed),
+        None => println!(),
+    }
+    Ok(())
+}
+
+fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Loads the art for each tile from `tileset`, scaled to `tile_size` in each
+/// of the four rotations. Art which fails to load is handled according to
+/// `missing_art`.
+fn load_tile_images(
+    specs: Vec<TileSpec>,
+    tileset: &Path,
+    missing_art: MissingArtPolicy,
+    tile_size: u32,
+) -> Result<(Vec<TileSpec>, Vec<RotatedArt>), Box<dyn std::error::Error>> {
+    let mut tiles = Vec::new();
+    let mut images = Vec::new();
+    for spec in specs {
+        let path = tileset
+            .join(spec.expansion.name())
+            .join(format!("{}.png", spec.art));
+        let art = match image::open(&path) {
+            Ok(art) => {
+                imageops::resize(&art.to_rgba8(), tile_size, tile_size, FilterType::Lanczos3)
+            }
+            Err(err) => {
+                let msg = format!(
+                    "image for tile art {} failed to load from {}: {}",
+                    spec.art,
+                    path.display(),
+                    err
+                );
+                match missing_art {
+                    MissingArtPolicy::Fail => return Err(msg.into()),
+                    MissingArtPolicy::Skip => {
+                        warn!("{}, removing tile {} from the deck", msg, spec.id);
+                        continue;
+                    }
+                    MissingArtPolicy::Placeholder => {
+                        warn!("{}, drawing a placeholder for tile {}", msg, spec.id);
+                        placeholder_art(&spec, tile_size)
+                    }
+                }
+            }
+        };
+        // Rotations turn the art anticlockwise, matching the web renderer
+        images.push([
+            art.clone(),
+            imageops::rotate270(&art),
+            imageops::rotate180(&art),
+            imageops::rotate90(&art),
+        ]);
+        tiles.push(spec);
+    }
+    Ok((tiles, images))
+}
+
+/// Draws a simple stand in for a tile's art, matching the web renderer.
+fn placeholder_art(spec: &TileSpec, size: u32) -> RgbaImage {
+    RgbaImage::from_fn(size, size, |px, py| {
+        // Position relative to the centre, in units of half the tile size
+        let x = (px as f64 + 0.5) / size as f64 * 2.0 - 1.0;
+        let y = (py as f64 + 0.5) / size as f64 * 2.0 - 1.0;
+        let mut colour = FIELD_COLOUR;
+        let (mut edge_x, mut edge_y) = (x, y);
+        for feature in spec.edge_features {
+            let inside = match feature {
+                Feature::City => edge_y <= CITY_DEPTH - 1.0 && edge_x.abs() <= -edge_y,
+                Feature::Road => edge_x.abs() <= ROAD_WIDTH && edge_y <= 0.0,
+                Feature::River => edge_x.abs() <= RIVER_WIDTH && edge_y <= 0.0,
+                Feature::None => false,
+            };
+            if inside {
+                colour = match feature {
+                    Feature::City => CITY_COLOUR,
+                    Feature::Road => ROAD_COLOUR,
+                    _ => RIVER_COLOUR,
+                };
+            }
+            // Turn the point so the next edge lines up with the north edge
+            (edge_x, edge_y) = (edge_y, -edge_x);
+        }
+        if spec.cloister && x.abs() <= CLOISTER_SIZE && y.abs() <= CLOISTER_SIZE {
+            colour = CLOISTER_COLOUR;
+        }
+        Rgba([colour[0], colour[1], colour[2], 255])
+    })
+}
+
+/// Draws the map, `images` holds the art for each entry in `Map::specs`.
+fn draw_map(map: &Map, images: &[RotatedArt], layout: &Layout) -> RgbaImage {
+    // A finite deck may have shrunk the map
+    let mut canvas = RgbaImage::new(
+        layout.canvas_width.min(map.size_x() * layout.tile_size),
+        layout.canvas_height.min(map.size_y() * layout.tile_size),
+    );
+    for y in 0..map.size_y() {
+        let pos_y = y as i64 * layout.tile_size as i64;
+        for x in 0..map.size_x() {
+            if let Some(tile) = map.tile(x as usize, y as usize) {
+                let pos_x = x as i64 * layout.tile_size as i64;
+                let art = &images[tile.tile_spec as usize][tile.rotation as usize];
+                imageops::overlay(&mut canvas, art, pos_x, pos_y);
+            }
+        }
+    }
+    canvas
+}
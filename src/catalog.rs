@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+
+use crate::tile::TileSpec;
+
+/// File name of the catalog shipped alongside each tile art set.
+pub const CATALOG_FILE_NAME: &str = "tiles.json";
+
+/// Most tiles a catalog may list, tiles are numbered with a `u8`.
+pub const MAX_CATALOG_TILES: usize = u8::MAX as usize + 1;
+
+/// Declarative list of the tiles available in a tile art set.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(try_from = "UncheckedCatalog")]
+pub struct Catalog {
+    pub tiles: Vec<TileSpec>,
+}
+
+/// A catalog as written, before its tiles are checked.
+#[derive(serde::Deserialize)]
+struct UncheckedCatalog {
+    tiles: Vec<TileSpec>,
+}
+
+/// Why a catalog's list of tiles cannot be used.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CatalogError {
+    /// More tiles are listed than can be numbered.
+    TooManyTiles(usize),
+    /// Two tiles share an id.
+    DuplicateId(String),
+    /// A tile comes in a negative number of copies.
+    NegativeCount(String),
+}
+
+impl std::fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CatalogError::TooManyTiles(count) => write!(
+                f,
+                "the catalog lists {} tiles, at most {} are allowed",
+                count, MAX_CATALOG_TILES
+            ),
+            CatalogError::DuplicateId(id) => write!(f, "tile {} is listed more than once", id),
+            CatalogError::NegativeCount(id) => write!(f, "tile {} has a negative count", id),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl TryFrom<UncheckedCatalog> for Catalog {
+    type Error = CatalogError;
+
+    fn try_from(catalog: UncheckedCatalog) -> Result<Self, Self::Error> {
+        let tiles = catalog.tiles;
+        if tiles.len() > MAX_CATALOG_TILES {
+            return Err(CatalogError::TooManyTiles(tiles.len()));
+        }
+        let mut ids = HashSet::new();
+        for spec in &tiles {
+            if !ids.insert(spec.id.as_str()) {
+                return Err(CatalogError::DuplicateId(spec.id.clone()));
+            }
+            if spec.count < 0 {
+                return Err(CatalogError::NegativeCount(spec.id.clone()));
+            }
+        }
+        Ok(Catalog { tiles })
+    }
+}
+
+impl Catalog {
+    /// Reads a catalog, refusing one whose tiles cannot be told apart or
+    /// numbered, or which come in a negative number of copies.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// What to do when the art for a tile fails to load.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingArtPolicy {
+    /// Stop with an error naming the art which failed.
+    Fail,
+    /// Remove the tile from the deck and generate without it.
+    Skip,
+    /// Keep the tile and draw a placeholder in place of its art.
+    Placeholder,
+}
+
+impl std::str::FromStr for MissingArtPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(MissingArtPolicy::Fail),
+            "skip" => Ok(MissingArtPolicy::Skip),
+            "placeholder" => Ok(MissingArtPolicy::Placeholder),
+            _ => Err(format!(
+                "unknown missing art policy {}, expected fail, skip or placeholder",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::map::Map;
+    use crate::tile::{Expansion, Feature};
+
+    /// Returns the tiles of the catalog shipped with the high resolution art.
+    pub(crate) fn highres() -> Vec<TileSpec> {
+        Catalog::from_json(include_str!("../tileart/highres/tiles.json"))
+            .unwrap()
+            .tiles
+    }
+
+    /// Returns the base game tiles of the high resolution catalog.
+    pub(crate) fn base() -> Vec<TileSpec> {
+        highres()
+            .into_iter()
+            .filter(|spec| spec.expansion == Expansion::Base)
+            .collect()
+    }
+
+    /// Returns a base game tile with the given edges, north, east, south and
+    /// west, and its regions left to their defaults.
+    pub(crate) fn tile(id: &str, edges: [Feature; 4]) -> TileSpec {
+        TileSpec {
+            id: id.to_string(),
+            expansion: Expansion::Base,
+            art: id.to_string(),
+            cloister: false,
+            sheild: false,
+            edge_features: edges,
+            river_end: None,
+            regions: Vec::new(),
+            count: 1,
+        }
+    }
+
+    /// Returns true if every tile on the map matches the tiles beside it and
+    /// the border.
+    pub(crate) fn edges_match(map: &Map) -> bool {
+        (0..map.size_y() as usize).all(|y| {
+            (0..map.size_x() as usize).all(|x| {
+                map.tile(x, y)
+                    .is_none_or(|tile| map.can_be_placed(tile.tile_spec, x, y, tile.rotation))
+            })
+        })
+    }
+
+    #[test]
+    fn parses_the_shipped_catalog() {
+        let tiles = highres();
+        assert_eq!(tiles.len(), 34);
+        let count = |expansion| {
+            tiles
+                .iter()
+                .filter(|spec| spec.expansion == expansion)
+                .map(|spec| spec.count)
+                .sum::<i32>()
+        };
+        assert_eq!(count(Expansion::Base), 71);
+        assert_eq!(count(Expansion::River), 10);
+        let mut ids: Vec<&str> = tiles.iter().map(|spec| spec.id.as_str()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), tiles.len());
+    }
+
+    #[test]
+    fn rejects_unknown_edge_features() {
+        let json = r#"{ "tiles": [{ "id": "a", "expansion": "base", "art": "a",
+            "edges": ["none", "castle", "none", "none"], "count": 1 }] }"#;
+        assert!(Catalog::from_json(json).is_err());
+    }
+
+    #[test]
+    fn rejects_tiles_which_cannot_be_used() {
+        let catalog = |tiles: &[(&str, i32)]| {
+            let tiles: Vec<String> = tiles
+                .iter()
+                .map(|(id, count)| {
+                    format!(
+                        r#"{{ "id": "{}", "expansion": "base", "art": "a",
+                        "edges": ["none", "none", "none", "none"], "count": {} }}"#,
+                        id, count
+                    )
+                })
+                .collect();
+            Catalog::from_json(&format!(r#"{{ "tiles": [{}] }}"#, tiles.join(", ")))
+        };
+        assert!(catalog(&[("a", 0), ("b", 2)]).is_ok());
+        let error = |tiles: &[(&str, i32)]| catalog(tiles).unwrap_err().to_string();
+        assert_eq!(error(&[("a", -1)]), "tile a has a negative count");
+        assert_eq!(
+            error(&[("a", 1), ("b", 1), ("a", 1)]),
+            "tile a is listed more than once"
+        );
+        let ids: Vec<String> = (0..=MAX_CATALOG_TILES).map(|n| n.to_string()).collect();
+        let many: Vec<(&str, i32)> = ids.iter().map(|id| (id.as_str(), 1)).collect();
+        assert!(catalog(&many[..MAX_CATALOG_TILES]).is_ok());
+        assert_eq!(
+            error(&many),
+            "the catalog lists 257 tiles, at most 256 are allowed"
+        );
+    }
+
+    #[test]
+    fn parses_missing_art_policies() {
+        assert_eq!("skip".parse(), Ok(MissingArtPolicy::Skip));
+        assert_eq!("placeholder".parse(), Ok(MissingArtPolicy::Placeholder));
+        assert!("ignore".parse::<MissingArtPolicy>().is_err());
+    }
+}
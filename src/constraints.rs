@@ -0,0 +1,489 @@
+//! Constraints on the features of a finished map.
+//!
+//! After the map is laid it is checked against the constraints using the
+//! map-wide feature analysis, and repaired a little at a time: a small window
+//! of tiles around a feature breaking a constraint is laid again, and the new
+//! tiles kept unless they leave the map further from meeting the constraints.
+//! Whatever is still unsatisfied after the rounds run out is reported.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use log::info;
+
+use crate::features::{find_features, FeatureKind, MapFeature};
+use crate::generator::{refill, replaceable, GeneratorConfig};
+use crate::map::Map;
+use crate::rng::Rng;
+
+/// Rounds of repair when not configured.
+pub const DEFAULT_REPAIR_ROUNDS: u32 = 200;
+
+/// Most cells from the chosen cell to the edge of the window laid again in
+/// each round of repair.
+const MAX_WINDOW_RADIUS: usize = 2;
+
+/// Properties the features of the map should have. Sizes and lengths are
+/// counted in tiles, and a city or road running off the map counts only the
+/// tiles on it.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Constraints {
+    /// Fewest tiles in any city.
+    pub min_city_size: Option<u32>,
+    /// Most tiles in any city.
+    pub max_city_size: Option<u32>,
+    /// Fewest cities closed off on every side.
+    pub completed_cities: Option<u32>,
+    /// Most tiles along any road.
+    pub max_road_length: Option<u32>,
+    /// Fewest cloisters on the map.
+    pub min_cloisters: Option<u32>,
+    /// Every city can be reached by road from every other: one network of
+    /// roads, joined where they share a tile, runs onto a tile of each city.
+    pub cities_on_roads: bool,
+    /// Times a window of the map is laid again to meet the constraints.
+    pub repair_rounds: u32,
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        Constraints {
+            min_city_size: None,
+            max_city_size: None,
+            completed_cities: None,
+            max_road_length: None,
+            min_cloisters: None,
+            cities_on_roads: false,
+            repair_rounds: DEFAULT_REPAIR_ROUNDS,
+        }
+    }
+}
+
+/// A constraint the map does not meet.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "constraint", rename_all = "kebab-case")]
+pub enum Unsatisfied {
+    /// The given number of cities are smaller than `min` tiles.
+    MinCitySize { min: u32, cities: u32 },
+    /// The given number of cities are larger than `max` tiles.
+    MaxCitySize { max: u32, cities: u32 },
+    /// Fewer than `wanted` cities are complete.
+    CompletedCities { wanted: u32, completed: u32 },
+    /// The given number of roads are longer than `max` tiles.
+    MaxRoadLength { max: u32, roads: u32 },
+    /// There are fewer than `wanted` cloisters.
+    MinCloisters { wanted: u32, cloisters: u32 },
+    /// The given number of cities cannot be reached by road from the rest.
+    CitiesOnRoads { cities: u32 },
+}
+
+impl std::fmt::Display for Unsatisfied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unsatisfied::MinCitySize { min, cities } => {
+                write!(f, "{} cities are smaller than {} tiles", cities, min)
+            }
+            Unsatisfied::MaxCitySize { max, cities } => {
+                write!(f, "{} cities are larger than {} tiles", cities, max)
+            }
+            Unsatisfied::CompletedCities { wanted, completed } => write!(
+                f,
+                "only {} cities are completed of the {} wanted",
+                completed, wanted
+            ),
+            Unsatisfied::MaxRoadLength { max, roads } => {
+                write!(f, "{} roads are longer than {} tiles", roads, max)
+            }
+            Unsatisfied::MinCloisters { wanted, cloisters } => write!(
+                f,
+                "only {} cloisters are on the map of the {} wanted",
+                cloisters, wanted
+            ),
+            Unsatisfied::CitiesOnRoads { cities } => {
+                write!(f, "{} cities cannot be reached by road", cities)
+            }
+        }
+    }
+}
+
+/// A constraint the map does not meet, with how far it is from meeting it
+/// and the cells where laying new tiles might help.
+struct Violation {
+    unsatisfied: Unsatisfied,
+    shortfall: u32,
+    cells: Vec<(usize, usize)>,
+}
+
+impl Constraints {
+    /// Returns true if no constraint is set.
+    pub fn is_empty(&self) -> bool {
+        self.min_city_size.is_none()
+            && self.max_city_size.is_none()
+            && self.completed_cities.is_none()
+            && self.max_road_length.is_none()
+            && self.min_cloisters.is_none()
+            && !self.cities_on_roads
+    }
+
+    /// Returns each constraint `map` does not meet.
+    pub fn check(&self, map: &Map) -> Vec<Unsatisfied> {
+        self.violations(map)
+            .into_iter()
+            .map(|violation| violation.unsatisfied)
+            .collect()
+    }
+
+    fn violations(&self, map: &Map) -> Vec<Violation> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let features = find_features(map).features;
+        let of_kind = |kind: FeatureKind| -> Vec<&MapFeature> {
+            features
+                .iter()
+                .filter(|feature| feature.kind == kind)
+                .collect()
+        };
+        let cities = of_kind(FeatureKind::City);
+        let size = |feature: &MapFeature| feature.tiles.len() as u32;
+        let mut violations = Vec::new();
+
+        if let Some(min) = self.min_city_size {
+            let small: Vec<&MapFeature> = cities
+                .iter()
+                .copied()
+                .filter(|&city| size(city) < min)
+                .collect();
+            violations.push(Violation {
+                unsatisfied: Unsatisfied::MinCitySize {
+                    min,
+                    cities: small.len() as u32,
+                },
+                shortfall: small.iter().map(|&city| min - size(city)).sum(),
+                cells: tiles(&small),
+            });
+        }
+        if let Some(max) = self.max_city_size {
+            let large: Vec<&MapFeature> = cities
+                .iter()
+                .copied()
+                .filter(|&city| size(city) > max)
+                .collect();
+            violations.push(Violation {
+                unsatisfied: Unsatisfied::MaxCitySize {
+                    max,
+                    cities: large.len() as u32,
+                },
+                shortfall: large.iter().map(|&city| size(city) - max).sum(),
+                cells: tiles(&large),
+            });
+        }
+        if let Some(wanted) = self.completed_cities {
+            let completed = cities.iter().filter(|city| city.complete).count() as u32;
+            // Closing off an open city is the likeliest way to complete one
+            let cells = cities
+                .iter()
+                .flat_map(|city| &city.open_edges)
+                .map(|edge| (edge.x as usize, edge.y as usize))
+                .collect();
+            violations.push(Violation {
+                unsatisfied: Unsatisfied::CompletedCities { wanted, completed },
+                shortfall: wanted.saturating_sub(completed),
+                cells,
+            });
+        }
+        if let Some(max) = self.max_road_length {
+            let long: Vec<&MapFeature> = of_kind(FeatureKind::Road)
+                .into_iter()
+                .filter(|&road| size(road) > max)
+                .collect();
+            violations.push(Violation {
+                unsatisfied: Unsatisfied::MaxRoadLength {
+                    max,
+                    roads: long.len() as u32,
+                },
+                shortfall: long.iter().map(|&road| size(road) - max).sum(),
+                cells: tiles(&long),
+            });
+        }
+        if let Some(wanted) = self.min_cloisters {
+            let cloisters = of_kind(FeatureKind::Cloister).len() as u32;
+            // A cloister may turn up wherever a tile is laid again
+            let cells = (0..map.size_y() as usize)
+                .flat_map(|y| (0..map.size_x() as usize).map(move |x| (x, y)))
+                .filter(|&(x, y)| map.tile(x, y).is_some())
+                .collect();
+            violations.push(Violation {
+                unsatisfied: Unsatisfied::MinCloisters { wanted, cloisters },
+                shortfall: wanted.saturating_sub(cloisters),
+                cells,
+            });
+        }
+        if self.cities_on_roads {
+            let networks = road_networks(&of_kind(FeatureKind::Road));
+            let reaches = |city: &MapFeature, network: usize| {
+                city.tiles
+                    .iter()
+                    .any(|cell| networks.get(cell) == Some(&network))
+            };
+            // The network reaching the most cities, the others to be joined
+            // onto it
+            let mut roots: Vec<usize> = networks.values().copied().collect();
+            roots.sort();
+            roots.dedup();
+            let main = roots.into_iter().max_by_key(|&network| {
+                let reached = cities.iter().filter(|city| reaches(city, network)).count();
+                (reached, Reverse(network))
+            });
+            let cut_off: Vec<&MapFeature> = cities
+                .iter()
+                .copied()
+                .filter(|city| !main.is_some_and(|network| reaches(city, network)))
+                .collect();
+            violations.push(Violation {
+                unsatisfied: Unsatisfied::CitiesOnRoads {
+                    cities: cut_off.len() as u32,
+                },
+                shortfall: cut_off.len() as u32,
+                cells: tiles(&cut_off),
+            });
+        }
+
+        violations.retain(|violation| violation.shortfall > 0);
+        violations
+    }
+
+    /// Lays windows of `map` around the features breaking the constraints
+    /// again for the configured number of rounds or until every constraint
+    /// is met, keeping the new tiles unless the map ends up further from
+    /// meeting the constraints. Use `check` for the constraints still not
+    /// met.
+    pub fn repair(&self, map: &mut Map, config: &GeneratorConfig, rng: &mut Rng) {
+        let mut violations = self.violations(map);
+        let mut shortfall = total_shortfall(&violations);
+        let mut rounds = 0;
+        while shortfall > 0 && rounds < self.repair_rounds {
+            rounds += 1;
+            let violation = &violations[rng.below(violations.len())];
+            if violation.cells.is_empty() {
+                continue;
+            }
+            let (x, y) = violation.cells[rng.below(violation.cells.len())];
+            let radius = 1 + rng.below(MAX_WINDOW_RADIUS);
+            let window = replaceable(map, window(map, x, y, radius));
+
+            let kept = map.tiles.clone();
+            if refill(map, window, config, rng).is_err() {
+                continue;
+            }
+            let repaired = self.violations(map);
+            match total_shortfall(&repaired) {
+                new if new <= shortfall => {
+                    shortfall = new;
+                    violations = repaired;
+                }
+                _ => map.tiles = kept,
+            }
+        }
+        if rounds > 0 {
+            info!(
+                "Repaired the map for {} rounds, {} constraints unsatisfied",
+                rounds,
+                violations.len()
+            );
+        }
+    }
+}
+
+/// Returns the network of roads on each cell any road covers, named by one
+/// of the roads in it. Roads sharing a tile, meeting at a crossroads or
+/// passing one another, are in the same network.
+fn road_networks(roads: &[&MapFeature]) -> HashMap<(u32, u32), usize> {
+    // Union-find over the roads, joining those on a cell already seen
+    let mut parent: Vec<usize> = (0..roads.len()).collect();
+    fn root(parent: &mut [usize], mut road: usize) -> usize {
+        while parent[road] != road {
+            parent[road] = parent[parent[road]];
+            road = parent[road];
+        }
+        road
+    }
+    let mut first: HashMap<(u32, u32), usize> = HashMap::new();
+    for (road, feature) in roads.iter().enumerate() {
+        for &cell in &feature.tiles {
+            let other = *first.entry(cell).or_insert(road);
+            let (a, b) = (root(&mut parent, road), root(&mut parent, other));
+            parent[a] = b;
+        }
+    }
+    first
+        .into_iter()
+        .map(|(cell, road)| (cell, root(&mut parent, road)))
+        .collect()
+}
+
+fn total_shortfall(violations: &[Violation]) -> u32 {
+    violations.iter().map(|violation| violation.shortfall).sum()
+}
+
+/// Returns every cell covered by `features`, each once.
+fn tiles(features: &[&MapFeature]) -> Vec<(usize, usize)> {
+    let mut cells: Vec<(usize, usize)> = features
+        .iter()
+        .flat_map(|feature| &feature.tiles)
+        .map(|&(x, y)| (x as usize, y as usize))
+        .collect();
+    cells.sort_by_key(|&(x, y)| (y, x));
+    cells.dedup();
+    cells
+}
+
+/// Returns the cells within `radius` of (`x`, `y`) on the map, wrapping
+/// where the map wraps, row by row.
+fn window(map: &Map, x: usize, y: usize, radius: usize) -> Vec<(usize, usize)> {
+    let radius = radius as i32;
+    let mut cells: Vec<(usize, usize)> = (-radius..=radius)
+        .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+        .filter_map(|(dx, dy)| map.cell(x as i32 + dx, y as i32 + dy))
+        .collect();
+    cells.sort_by_key(|&(x, y)| (y, x));
+    cells.dedup();
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::{edges_match, highres, tile};
+    use crate::generator::generate_map;
+    use crate::map::PlacedTile;
+    use crate::tile::Feature::{City, None as Field};
+
+    /// Two city caps facing each other: one complete city of two tiles, no
+    /// roads and no cloisters.
+    fn city_caps() -> Map {
+        let mut map = Map::new(vec![tile("cityn", [City, Field, Field, Field])], 2, 1);
+        for (x, rotation) in [(0, 3), (1, 1)] {
+            map.tiles[0][x] = Some(PlacedTile {
+                tile_spec: 0,
+                rotation,
+            });
+        }
+        map
+    }
+
+    #[test]
+    fn an_unconstrained_map_is_satisfied() {
+        assert!(Constraints::default().is_empty());
+        assert_eq!(Constraints::default().check(&city_caps()), vec![]);
+    }
+
+    #[test]
+    fn reports_each_unmet_constraint() {
+        let constraints = Constraints {
+            min_city_size: Some(3),
+            max_city_size: Some(2),
+            completed_cities: Some(2),
+            max_road_length: Some(1),
+            min_cloisters: Some(1),
+            cities_on_roads: true,
+            ..Constraints::default()
+        };
+        assert_eq!(
+            constraints.check(&city_caps()),
+            vec![
+                Unsatisfied::MinCitySize { min: 3, cities: 1 },
+                Unsatisfied::CompletedCities {
+                    wanted: 2,
+                    completed: 1
+                },
+                Unsatisfied::MinCloisters {
+                    wanted: 1,
+                    cloisters: 0
+                },
+                Unsatisfied::CitiesOnRoads { cities: 1 },
+            ]
+        );
+    }
+
+    /// Lays the tiles with the given ids and rotations in a row.
+    fn row(tiles: &[(&str, u8)]) -> Map {
+        let mut map = Map::new(highres(), tiles.len() as u32, 1);
+        for (x, &(id, rotation)) in tiles.iter().enumerate() {
+            let tile_spec = map.specs().iter().position(|spec| spec.id == id).unwrap() as u8;
+            map.tiles[0][x] = Some(PlacedTile {
+                tile_spec,
+                rotation,
+            });
+        }
+        assert!(edges_match(&map));
+        map
+    }
+
+    #[test]
+    fn cities_must_share_one_road_network() {
+        let constraints = Constraints {
+            cities_on_roads: true,
+            ..Constraints::default()
+        };
+        // One road running from the city on the left to the one on the right
+        let joined = row(&[("cityn_roadew", 0), ("roadns", 1), ("cityn_roadsw", 0)]);
+        assert_eq!(constraints.check(&joined), vec![]);
+        // Each city has a road, but the left one ends at a cloister
+        let apart = row(&[
+            ("cityn_roadew", 0),
+            ("cloister_roads", 3),
+            ("cityn_roades", 0),
+        ]);
+        assert_eq!(
+            constraints.check(&apart),
+            vec![Unsatisfied::CitiesOnRoads { cities: 1 }]
+        );
+        // Roads meeting at a crossroads join their networks
+        let crossing = row(&[
+            ("cityn_roadew", 0),
+            ("cityn_roadesw", 0),
+            ("cityn_roadsw", 0),
+        ]);
+        assert_eq!(constraints.check(&crossing), vec![]);
+    }
+
+    #[test]
+    fn repair_brings_the_map_closer() {
+        let generate = |repair_rounds| {
+            let config = GeneratorConfig {
+                constraints: Constraints {
+                    max_road_length: Some(3),
+                    repair_rounds,
+                    ..Constraints::default()
+                },
+                ..GeneratorConfig::default()
+            };
+            generate_map(highres(), 12, 9, &config, &mut Rng::new(5)).unwrap()
+        };
+        let limit = Constraints {
+            max_road_length: Some(3),
+            ..Constraints::default()
+        };
+        let long_roads = |map: &Map| match limit.check(map).as_slice() {
+            [Unsatisfied::MaxRoadLength { roads, .. }] => *roads,
+            _ => 0,
+        };
+        let unrepaired = generate(0);
+        let repaired = generate(DEFAULT_REPAIR_ROUNDS);
+        assert!(long_roads(&unrepaired) > 0);
+        assert!(long_roads(&repaired) < long_roads(&unrepaired));
+        assert!(edges_match(&repaired));
+        assert!(repaired.tiles.iter().flatten().all(Option::is_some));
+    }
+
+    #[test]
+    fn parses_constraints() {
+        let constraints: Constraints =
+            serde_json::from_str(r#"{"min_city_size": 4, "cities_on_roads": true}"#).unwrap();
+        assert_eq!(constraints.min_city_size, Some(4));
+        assert!(constraints.cities_on_roads);
+        assert_eq!(constraints.repair_rounds, DEFAULT_REPAIR_ROUNDS);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::solver::Candidate;
+use crate::tile::TileSpec;
+
+/// Most powers of two a tile's weight is raised or lowered by for being
+/// behind or ahead of its target share.
+const MAX_STEER: i64 = 8;
+
+/// The tiles left to draw while generating a map.
+///
+/// An unlimited deck puts every tile back after it is drawn, so any tile may
+/// be used any number of times. A finite deck holds the tiles from a number of
+/// copies of the game and each tile placed is removed from it.
+#[derive(Clone, Debug)]
+pub struct Deck {
+    /// Number of each tile left, indexed like `Map::specs`, `None` for an
+    /// unlimited deck.
+    remaining: Option<Vec<u32>>,
+    /// Number of each tile the deck started with.
+    initial: Vec<u32>,
+    /// Number of each tile drawn and not put back.
+    placed: Vec<u32>,
+    /// Share of the map each tile should make up, when choices are steered
+    /// towards it.
+    targets: Option<Vec<u32>>,
+}
+
+impl Deck {
+    pub fn unlimited() -> Self {
+        Deck {
+            remaining: None,
+            initial: Vec::new(),
+            placed: Vec::new(),
+            targets: None,
+        }
+    }
+
+    /// Holds `copies` of every tile in `specs`, as many times as it comes in
+    /// the box.
+    pub fn finite(specs: &[TileSpec], copies: u32) -> Self {
+        let initial: Vec<u32> = specs
+            .iter()
+            .map(|spec| spec.count.max(0) as u32 * copies)
+            .collect();
+        Deck {
+            remaining: Some(initial.clone()),
+            initial,
+            placed: Vec::new(),
+            targets: None,
+        }
+    }
+
+    /// Steers the tiles drawn so each makes up close to its share of
+    /// `targets`, indexed like `Map::specs`. Tiles with no target are only
+    /// drawn when nothing else fits.
+    pub fn with_targets(mut self, targets: Vec<u32>) -> Self {
+        self.targets = Some(targets);
+        self
+    }
+
+    /// Returns the number of tiles left, `None` if the deck is unlimited.
+    pub fn size(&self) -> Option<usize> {
+        self.remaining
+            .as_ref()
+            .map(|remaining| remaining.iter().map(|&n| n as usize).sum())
+    }
+
+    /// Returns the number of `tile` left, which is endless in an unlimited
+    /// deck.
+    pub fn count(&self, tile: u8) -> u32 {
+        match &self.remaining {
+            Some(remaining) => remaining[tile as usize],
+            None => u32::MAX,
+        }
+    }
+
+    pub fn has(&self, tile: u8) -> bool {
+        match &self.remaining {
+            Some(remaining) => remaining[tile as usize] > 0,
+            None => true,
+        }
+    }
+
+    /// Removes one of `tile` from the deck, it must be in the deck.
+    pub fn take(&mut self, tile: u8) {
+        if let Some(remaining) = &mut self.remaining {
+            remaining[tile as usize] -= 1;
+        }
+        if self.placed.len() <= tile as usize {
+            self.placed.resize(tile as usize + 1, 0);
+        }
+        self.placed[tile as usize] += 1;
+    }
+
+    /// Returns a tile taken from the deck.
+    pub fn put_back(&mut self, tile: u8) {
+        if let Some(remaining) = &mut self.remaining {
+            remaining[tile as usize] += 1;
+        }
+        if let Some(placed) = self.placed.get_mut(tile as usize) {
+            *placed = placed.saturating_sub(1);
+        }
+    }
+
+    /// Returns whether `tile` is steered towards a share of the deck, every
+    /// tile is when the deck has no targets.
+    pub fn is_targeted(&self, tile: u8) -> bool {
+        match &self.targets {
+            Some(targets) => targets.get(tile as usize).is_some_and(|&target| target > 0),
+            None => true,
+        }
+    }
+
+    /// Returns the weight `candidate` is drawn by. When the deck has targets
+    /// its weight is doubled for each tile it is behind its share of the
+    /// tiles placed so far and halved for each tile it is ahead.
+    pub fn weight(&self, candidate: &Candidate) -> u64 {
+        let Some(targets) = &self.targets else {
+            return candidate.weight as u64;
+        };
+        let placed = |tile: usize| self.placed.get(tile).copied().unwrap_or_default() as i64;
+        let total: i64 = targets.iter().map(|&target| target as i64).sum();
+        let drawn: i64 = (0..targets.len())
+            .filter(|&tile| targets[tile] > 0)
+            .map(placed)
+            .sum();
+        let tile = candidate.tile as usize;
+        let shift = match targets.get(tile).copied().unwrap_or_default() as i64 {
+            0 => -MAX_STEER,
+            // How far the tile falls short of its share once the next is placed
+            target => (target * (drawn + 1) - placed(tile) * total)
+                .div_euclid(total)
+                .clamp(-MAX_STEER, MAX_STEER),
+        };
+        (candidate.weight.max(1) as u64) << (shift + MAX_STEER)
+    }
+
+    /// Returns the tiles which came in the deck and have all been used.
+    pub fn exhausted(&self) -> Vec<u8> {
+        let Some(remaining) = &self.remaining else {
+            return Vec::new();
+        };
+        remaining
+            .iter()
+            .zip(&self.initial)
+            .enumerate()
+            .filter(|(_, (&n, &initial))| initial > 0 && n == 0)
+            .map(|(tile, _)| tile as u8)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::tile;
+    use crate::tile::Feature;
+
+    fn specs() -> Vec<TileSpec> {
+        let mut specs = vec![
+            tile("cloister", [Feature::None; 4]),
+            tile(
+                "roadns",
+                [Feature::Road, Feature::None, Feature::Road, Feature::None],
+            ),
+        ];
+        specs[1].count = 3;
+        specs
+    }
+
+    fn candidate(tile: u8) -> Candidate {
+        Candidate {
+            tile,
+            rotation: 0,
+            weight: 4,
+        }
+    }
+
+    #[test]
+    fn a_finite_deck_runs_out() {
+        let mut deck = Deck::finite(&specs(), 2);
+        assert_eq!(deck.size(), Some(8));
+        assert_eq!(deck.count(0), 2);
+        deck.take(0);
+        deck.take(0);
+        assert!(!deck.has(0));
+        assert!(deck.has(1));
+        assert_eq!(deck.exhausted(), vec![0]);
+        deck.put_back(0);
+        assert!(deck.has(0));
+        assert_eq!(deck.size(), Some(7));
+        assert!(deck.exhausted().is_empty());
+    }
+
+    #[test]
+    fn an_unlimited_deck_never_runs_out() {
+        let mut deck = Deck::unlimited();
+        for _ in 0..10 {
+            deck.take(1);
+        }
+        assert!(deck.has(1));
+        assert_eq!(deck.size(), None);
+        assert_eq!(deck.count(1), u32::MAX);
+        assert!(deck.exhausted().is_empty());
+    }
+
+    #[test]
+    fn targets_favour_tiles_behind_their_share() {
+        let mut deck = Deck::unlimited().with_targets(vec![1, 1]);
+        assert_eq!(deck.weight(&candidate(0)), deck.weight(&candidate(1)));
+        for _ in 0..3 {
+            deck.take(0);
+        }
+        assert!(deck.weight(&candidate(1)) > deck.weight(&candidate(0)));
+        let untargeted = Deck::unlimited().with_targets(vec![1, 0]);
+        assert!(untargeted.weight(&candidate(1)) < untargeted.weight(&candidate(0)));
+    }
+
+    #[test]
+    fn tiles_without_a_target_are_not_targeted() {
+        let deck = Deck::unlimited().with_targets(vec![1, 0]);
+        assert!(deck.is_targeted(0));
+        assert!(!deck.is_targeted(1));
+        assert!(Deck::unlimited().is_targeted(1));
+    }
+
+    #[test]
+    fn without_targets_the_candidate_weight_is_used() {
+        assert_eq!(Deck::unlimited().weight(&candidate(0)), 4);
+    }
+}
@@ -0,0 +1,296 @@
+//! Density fields varying the character of the map from place to place.
+//!
+//! A field gives each cell a level from wild to urban, either Perlin noise,
+//! blended from random gradients on a coarse lattice, or a grayscale image.
+//! Tiles suiting the level of a cell are weighted up there and the rest
+//! weighted down.
+
+use crate::error::GenerateError;
+use crate::map::Map;
+use crate::rng::Rng;
+use crate::shape;
+use crate::tile::{Feature, TileSpec};
+
+/// Tiles across a feature of the noise field when not configured.
+pub const DEFAULT_DENSITY_SCALE: u32 = 8;
+
+/// Percentage strength of the density field when not configured.
+pub const DEFAULT_DENSITY_STRENGTH: u32 = 100;
+
+/// Most powers of two a tile's weight is raised or lowered by in the most
+/// urban or wildest cells.
+const MAX_BIAS: i64 = 8;
+
+/// Where the density of each cell comes from, from wild where it is low to
+/// urban where it is high.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DensityField {
+    /// The same everywhere.
+    #[default]
+    None,
+    /// Smooth Perlin noise drawn from the map's seed.
+    Noise,
+    /// A grayscale PNG image stretched over the map, light pixels urban and
+    /// dark ones wild.
+    Png(Vec<u8>),
+}
+
+impl std::str::FromStr for DensityField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DensityField::None),
+            "noise" => Ok(DensityField::Noise),
+            _ => Err(format!(
+                "unknown density field {}, expected none or noise",
+                s
+            )),
+        }
+    }
+}
+
+/// Options for varying the character of the map from place to place.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Density {
+    pub field: DensityField,
+    /// Tiles across each urban or wild region of a noise field.
+    pub scale: u32,
+    /// Percentage of how strongly urban cells favour cities and wild cells
+    /// favour fields and cloisters.
+    pub strength: u32,
+}
+
+impl Default for Density {
+    fn default() -> Self {
+        Density {
+            field: DensityField::None,
+            scale: DEFAULT_DENSITY_SCALE,
+            strength: DEFAULT_DENSITY_STRENGTH,
+        }
+    }
+}
+
+impl Density {
+    /// Returns how urban each cell of a `size_x` by `size_y` map is, row by
+    /// row, from -128 for the wildest to 127 for the most urban scaled by the
+    /// strength, or `None` when the map is the same everywhere.
+    pub fn bias(
+        &self,
+        size_x: u32,
+        size_y: u32,
+        rng: &mut Rng,
+    ) -> Result<Option<Vec<Vec<i32>>>, GenerateError> {
+        let levels = match &self.field {
+            DensityField::None => return Ok(None),
+            DensityField::Noise => noise(size_x, size_y, self.scale.max(1), rng),
+            DensityField::Png(bytes) => shape::sample_png(bytes, size_x, size_y)
+                .map_err(|err| GenerateError::InvalidDensity(err.to_string()))?
+                .into_iter()
+                .map(|row| row.into_iter().map(|[luma, _]| luma).collect())
+                .collect(),
+        };
+        let strength = self.strength as i64;
+        // However strong, no cell is biased further than a tile can be scaled
+        let limit = 128 * MAX_BIAS;
+        Ok(Some(
+            levels
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|level| {
+                            ((level as i64 - 128) * strength / 100).clamp(-limit, limit) as i32
+                        })
+                        .collect()
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// Returns how urban a tile is: one for each city edge, less one for each
+/// edge of bare field and two for a cloister.
+fn character(spec: &TileSpec) -> i64 {
+    let edges: i64 = spec
+        .edge_features
+        .iter()
+        .map(|feature| match feature {
+            Feature::City => 1,
+            Feature::None => -1,
+            _ => 0,
+        })
+        .sum();
+    match spec.cloister {
+        true => edges - 2,
+        false => edges,
+    }
+}
+
+/// Scales `weight` for placing `tile` at (`x`, `y`) by how well the tile
+/// suits the density there, doubling it for each step it suits the cell and
+/// halving it for each step it does not. Weights on a map without a density
+/// field are left alone.
+pub fn biased(map: &Map, weight: u64, tile: u8, x: usize, y: usize) -> u64 {
+    let Some(bias) = map.density(x, y) else {
+        return weight;
+    };
+    let shift =
+        (bias as i64 * character(&map.specs()[tile as usize]) / 128).clamp(-MAX_BIAS, MAX_BIAS);
+    weight << (shift + MAX_BIAS)
+}
+
+/// Unit gradients of the noise lattice, the axes and diagonals.
+const GRADIENTS: [(i64, i64); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (-1, 1),
+    (1, -1),
+    (-1, -1),
+];
+
+/// Returns Perlin noise from 0 to 255 for each cell, summing two octaves of
+/// random gradients on a lattice `scale` tiles apart.
+fn noise(size_x: u32, size_y: u32, scale: u32, rng: &mut Rng) -> Vec<Vec<u8>> {
+    let (size_x, size_y) = (size_x as usize, size_y as usize);
+    let mut total = vec![vec![0i64; size_x]; size_y];
+    for (scale, amplitude) in [(scale as usize, 2), ((scale as usize / 2).max(1), 1)] {
+        let (across, down) = (size_x / scale + 2, size_y / scale + 2);
+        let lattice: Vec<Vec<(i64, i64)>> = (0..down)
+            .map(|_| {
+                (0..across)
+                    .map(|_| GRADIENTS[rng.below(GRADIENTS.len())])
+                    .collect()
+            })
+            .collect();
+        for (y, row) in total.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                // Position between lattice points in 256ths
+                let (lx, ly) = (x / scale, y / scale);
+                let fx = ((x % scale) * 256 / scale) as i64;
+                let fy = ((y % scale) * 256 / scale) as i64;
+                // How far each corner's gradient points towards the cell
+                let slope = |cx: usize, cy: usize| {
+                    let (gx, gy) = lattice[ly + cy][lx + cx];
+                    gx * (fx - 256 * cx as i64) + gy * (fy - 256 * cy as i64)
+                };
+                let (ex, ey) = (fade(fx), fade(fy));
+                let top = slope(0, 0) * (256 - ex) + slope(1, 0) * ex;
+                let bottom = slope(0, 1) * (256 - ex) + slope(1, 1) * ex;
+                *cell += amplitude * ((top * (256 - ey) + bottom * ey) >> 16);
+            }
+        }
+    }
+    // Spread the sum back out across the full range
+    let (low, high) = total
+        .iter()
+        .flatten()
+        .fold((i64::MAX, i64::MIN), |(low, high), &n| {
+            (low.min(n), high.max(n))
+        });
+    total
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|n| ((n - low) * 255 / (high - low).max(1)) as u8)
+                .collect()
+        })
+        .collect()
+}
+
+/// Eases `t`, from 0 to 256, with Perlin's quintic so it starts and ends
+/// slowly and the noise curves smoothly across lattice lines.
+fn fade(t: i64) -> i64 {
+    t * t * t * (t * (6 * t - 15 * 256) + 10 * 256 * 256) / (256 * 256 * 256 * 256)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::base;
+
+    fn noise(strength: u32, seed: u64) -> Vec<Vec<i32>> {
+        Density {
+            field: DensityField::Noise,
+            scale: 4,
+            strength,
+        }
+        .bias(16, 12, &mut Rng::new(seed))
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn no_field_leaves_the_map_even() {
+        let mut rng = Rng::new(1);
+        assert_eq!(Density::default().bias(8, 8, &mut rng), Ok(None));
+        // Nothing is drawn, so seeds without a field are unchanged
+        assert_eq!(rng.next_u64(), Rng::new(1).next_u64());
+    }
+
+    #[test]
+    fn noise_spans_urban_to_wild() {
+        let bias = noise(100, 2);
+        assert_eq!(bias.len(), 12);
+        assert_eq!(bias[0].len(), 16);
+        let levels: Vec<i32> = bias.iter().flatten().copied().collect();
+        assert_eq!(levels.iter().min(), Some(&-128));
+        assert_eq!(levels.iter().max(), Some(&127));
+        assert_eq!(bias, noise(100, 2));
+        assert_ne!(bias, noise(100, 3));
+        let half: Vec<i32> = noise(50, 2).into_iter().flatten().collect();
+        assert_eq!(half.iter().min(), Some(&-64));
+    }
+
+    #[test]
+    fn a_huge_strength_saturates() {
+        let bias = noise(u32::MAX, 2);
+        let levels: Vec<i32> = bias.iter().flatten().copied().collect();
+        assert_eq!(levels.iter().min(), Some(&(-128 * MAX_BIAS as i32)));
+        assert_eq!(levels.iter().max(), Some(&(128 * MAX_BIAS as i32)));
+    }
+
+    #[test]
+    fn noise_changes_smoothly() {
+        let bias = noise(100, 4);
+        for row in &bias {
+            for pair in row.windows(2) {
+                assert!((pair[0] - pair[1]).abs() < 128);
+            }
+        }
+    }
+
+    #[test]
+    fn fade_eases_in_and_out() {
+        assert_eq!((fade(0), fade(128), fade(256)), (0, 128, 256));
+        assert!(fade(16) < 16 && fade(240) > 240);
+    }
+
+    #[test]
+    fn urban_cells_favour_cities() {
+        let specs = base();
+        let find = |id: &str| specs.iter().position(|spec| spec.id == id).unwrap() as u8;
+        let (city, cloister) = (find("citynesw_shield"), find("cloister"));
+        let map = Map::new(specs.clone(), 2, 1).with_density(Some(vec![vec![127, -128]]));
+        assert!(biased(&map, 4, city, 0, 0) > biased(&map, 4, cloister, 0, 0));
+        assert!(biased(&map, 4, city, 1, 0) < biased(&map, 4, cloister, 1, 0));
+        let even = Map::new(specs, 2, 1);
+        assert_eq!(biased(&even, 4, city, 0, 0), 4);
+    }
+
+    #[test]
+    fn a_broken_image_is_an_error() {
+        let density = Density {
+            field: DensityField::Png(b"not a png".to_vec()),
+            ..Density::default()
+        };
+        assert!(matches!(
+            density.bias(4, 4, &mut Rng::new(0)),
+            Err(GenerateError::InvalidDensity(_))
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+/// Reasons map generation can fail.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GenerateError {
+    /// The river could not be laid out on the map.
+    RiverPlacementFailed,
+    /// No tile in the deck fits the cell at the given position, whatever is
+    /// placed around it.
+    NoTileFits { x: usize, y: usize },
+    /// The solver gave up filling the map after backtracking too many times.
+    FillFailed { backtracks: usize },
+    /// Wave function collapse reached a cell with no tile left to place on
+    /// each of the given number of attempts.
+    CollapseFailed { attempts: usize },
+    /// The map has more cells than there are tiles in the deck.
+    DeckTooSmall { cells: usize, tiles: usize },
+    /// The map could not be filled once the tiles with the given ids had all
+    /// been used.
+    DeckExhausted { tiles: Vec<String> },
+    /// A pinned tile names a tile which is not in the catalog.
+    UnknownTile { id: String },
+    /// A tile is pinned outside the map or its shape, or turned other than 0
+    /// to 3 times.
+    InvalidPin { x: u32, y: u32 },
+    /// The tile pinned at the given position does not fit the border or the
+    /// tiles pinned before it.
+    PinConflict { x: u32, y: u32 },
+    /// Every base game tile is weighted 0, so none can ever be chosen.
+    NoTileWeighted,
+    /// The image giving the shape of the map could not be read.
+    InvalidShape(String),
+    /// The image given for the density field could not be read.
+    InvalidDensity(String),
+}
+
+impl std::fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerateError::RiverPlacementFailed => write!(f, "failed to place the river tiles"),
+            GenerateError::NoTileFits { x, y } => {
+                write!(f, "no tile in the deck fits at ({}, {})", x, y)
+            }
+            GenerateError::FillFailed { backtracks } => write!(
+                f,
+                "failed to fill the map with the deck after {} backtracks",
+                backtracks
+            ),
+            GenerateError::CollapseFailed { attempts } => write!(
+                f,
+                "wave function collapse failed to fill the map in {} attempts",
+                attempts
+            ),
+            GenerateError::DeckTooSmall { cells, tiles } => write!(
+                f,
+                "the map has {} cells but the deck only has {} tiles",
+                cells, tiles
+            ),
+            GenerateError::DeckExhausted { tiles } => {
+                write!(f, "ran out of tiles: {}", tiles.join(", "))
+            }
+            GenerateError::UnknownTile { id } => write!(f, "no tile with id {}", id),
+            GenerateError::InvalidPin { x, y } => write!(
+                f,
+                "the tile pinned at ({}, {}) is off the map or has a rotation other than 0 to 3",
+                x, y
+            ),
+            GenerateError::PinConflict { x, y } => write!(
+                f,
+                "the tile pinned at ({}, {}) does not fit the border or the tiles pinned before it",
+                x, y
+            ),
+            GenerateError::NoTileWeighted => write!(f, "every base tile is weighted 0"),
+            GenerateError::InvalidShape(msg) => write!(f, "invalid map shape: {}", msg),
+            GenerateError::InvalidDensity(msg) => write!(f, "invalid density field: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_read_as_sentences() {
+        assert_eq!(
+            GenerateError::NoTileFits { x: 3, y: 4 }.to_string(),
+            "no tile in the deck fits at (3, 4)"
+        );
+        assert_eq!(
+            GenerateError::DeckTooSmall {
+                cells: 100,
+                tiles: 71
+            }
+            .to_string(),
+            "the map has 100 cells but the deck only has 71 tiles"
+        );
+        assert_eq!(
+            GenerateError::DeckExhausted {
+                tiles: vec!["cityn".to_string(), "roadns".to_string()]
+            }
+            .to_string(),
+            "ran out of tiles: cityn, roadns"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use crate::map::Map;
+use crate::tile::{RegionKind, Segment};
+
+/// Offsets to the eight cells around a cloister.
+const SURROUNDING: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeatureKind {
+    City,
+    Road,
+    River,
+    Field,
+    Cloister,
+}
+
+impl From<RegionKind> for FeatureKind {
+    fn from(kind: RegionKind) -> Self {
+        match kind {
+            RegionKind::City => FeatureKind::City,
+            RegionKind::Road => FeatureKind::Road,
+            RegionKind::River => FeatureKind::River,
+            RegionKind::Field => FeatureKind::Field,
+        }
+    }
+}
+
+/// An edge of a tile, north, east, south or west, which a feature runs off
+/// without a tile beside it to continue onto.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct OpenEdge {
+    pub x: u32,
+    pub y: u32,
+    pub edge: u8,
+}
+
+/// A whole city, road, river, field or cloister on the map.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct MapFeature {
+    pub kind: FeatureKind,
+    /// Cells the feature covers, each listed once. For a cloister this is the
+    /// cloister itself followed by the tiles placed around it.
+    pub tiles: Vec<(u32, u32)>,
+    pub open_edges: Vec<OpenEdge>,
+    /// True once nothing more can be added to the feature: it has no open
+    /// edges, or for a cloister all eight cells around it hold tiles.
+    pub complete: bool,
+    /// Number of tiles with a shield in a city.
+    pub shields: u32,
+}
+
+/// Every feature on a map, and which feature each part of each tile belongs
+/// to.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FeatureGraph {
+    pub features: Vec<MapFeature>,
+    #[serde(skip)]
+    size_x: usize,
+    /// Feature touching each segment of each cell, as seen on the map after
+    /// rotating the tile.
+    #[serde(skip)]
+    segments: Vec<[Option<usize>; 12]>,
+    /// Cloister feature for each cell.
+    #[serde(skip)]
+    cloisters: Vec<Option<usize>>,
+}
+
+impl FeatureGraph {
+    /// Returns the index in `features` of the feature touching `segment` of
+    /// the tile at the given position, `segment` being where it lies on the
+    /// map rather than on the unrotated tile.
+    pub fn feature_at(&self, x: usize, y: usize, segment: Segment) -> Option<usize> {
+        self.segments[y * self.size_x + x][segment.index()]
+    }
+
+    /// Returns the index in `features` of the cloister at the given
+    /// position.
+    pub fn cloister_at(&self, x: usize, y: usize) -> Option<usize> {
+        self.cloisters[y * self.size_x + x]
+    }
+}
+
+/// Finds every city, road, river, field and cloister on the map by joining
+/// the regions of each tile with the matching regions of its neighbours.
+pub fn find_features(map: &Map) -> FeatureGraph {
+    let size_x = map.size_x() as usize;
+    let size_y = map.size_y() as usize;
+    let regions: Vec<_> = map
+        .specs()
+        .iter()
+        .map(|spec| spec.connected_regions())
+        .collect();
+
+    // One node per region of each placed tile
+    let mut kinds = Vec::new();
+    let mut node_cells = Vec::new();
+    let mut segment_nodes = vec![[None; 12]; size_x * size_y];
+    for y in 0..size_y {
+        for x in 0..size_x {
+            let Some(tile) = map.tile(x, y) else {
+                continue;
+            };
+            for region in &regions[tile.tile_spec as usize] {
+                let node = kinds.len();
+                kinds.push(region.kind);
+                node_cells.push((x, y));
+                for segment in &region.segments {
+                    segment_nodes[y * size_x + x][segment.rotated(tile.rotation).index()] =
+                        Some(node);
+                }
+            }
+        }
+    }
+
+    // Join each region with the one it touches across every edge
+    let mut sets = DisjointSets::new(kinds.len());
+    let mut open = Vec::new();
+    for y in 0..size_y {
+        for x in 0..size_x {
+            for segment in Segment::ALL {
+                let Some(node) = segment_nodes[y * size_x + x][segment.index()] else {
+                    continue;
+                };
+                let other = map
+                    .neighbour(x, y, segment.edge())
+                    .and_then(|(new_x, new_y)| {
+                        segment_nodes[new_y * size_x + new_x][segment.opposite().index()]
+                    });
+                match other {
+                    Some(other) => sets.join(node, other),
+                    None => open.push((
+                        node,
+                        OpenEdge {
+                            x: x as u32,
+                            y: y as u32,
+                            edge: segment.edge() as u8,
+                        },
+                    )),
+                }
+            }
+        }
+    }
+
+    let mut features: Vec<MapFeature> = Vec::new();
+    let mut node_features = vec![None; kinds.len()];
+    for node in 0..kinds.len() {
+        let root = sets.find(node);
+        let index = match node_features[root] {
+            Some(index) => index,
+            None => {
+                features.push(MapFeature {
+                    kind: kinds[root].into(),
+                    tiles: Vec::new(),
+                    open_edges: Vec::new(),
+                    complete: false,
+                    shields: 0,
+                });
+                node_features[root] = Some(features.len() - 1);
+                features.len() - 1
+            }
+        };
+        node_features[node] = Some(index);
+
+        let feature = &mut features[index];
+        let (x, y) = node_cells[node];
+        // A tile's regions are numbered together, so repeats are adjacent
+        if feature.tiles.last() != Some(&(x as u32, y as u32)) {
+            feature.tiles.push((x as u32, y as u32));
+            let spec = &map.specs()[map.tile(x, y).unwrap().tile_spec as usize];
+            if feature.kind == FeatureKind::City && spec.sheild {
+                feature.shields += 1;
+            }
+        }
+    }
+    for (node, edge) in open {
+        let feature = &mut features[node_features[node].unwrap()];
+        if feature.open_edges.last() != Some(&edge) {
+            feature.open_edges.push(edge);
+        }
+    }
+    for feature in &mut features {
+        feature.complete = feature.open_edges.is_empty();
+    }
+
+    let segments = segment_nodes
+        .iter()
+        .map(|nodes| nodes.map(|node| node.and_then(|node| node_features[node])))
+        .collect();
+
+    let mut cloisters = vec![None; size_x * size_y];
+    for y in 0..size_y {
+        for x in 0..size_x {
+            let Some(tile) = map.tile(x, y) else {
+                continue;
+            };
+            if !map.specs()[tile.tile_spec as usize].cloister {
+                continue;
+            }
+            let mut tiles = vec![(x as u32, y as u32)];
+            for (dx, dy) in SURROUNDING {
+                let Some((new_x, new_y)) = map.cell(x as i32 + dx, y as i32 + dy) else {
+                    continue;
+                };
+                let cell = (new_x as u32, new_y as u32);
+                // Small wrapping maps may reach the same cell more than once
+                if map.tile(new_x, new_y).is_some() && !tiles.contains(&cell) {
+                    tiles.push(cell);
+                }
+            }
+            cloisters[y * size_x + x] = Some(features.len());
+            features.push(MapFeature {
+                kind: FeatureKind::Cloister,
+                complete: tiles.len() == SURROUNDING.len() + 1,
+                tiles,
+                open_edges: Vec::new(),
+                shields: 0,
+            });
+        }
+    }
+
+    FeatureGraph {
+        features,
+        size_x,
+        segments,
+        cloisters,
+    }
+}
+
+/// Union find over the regions of every tile.
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(size: usize) -> Self {
+        DisjointSets {
+            parents: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, mut node: usize) -> usize {
+        while self.parents[node] != node {
+            self.parents[node] = self.parents[self.parents[node]];
+            node = self.parents[node];
+        }
+        node
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        // Keep the earliest node as the root so features are listed in the
+        // order their first tile appears
+        self.parents[a.max(b)] = a.min(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::tile;
+    use crate::map::{PlacedTile, Wrap};
+    use crate::tile::Feature::{City, None as Field, Road};
+
+    fn place(map: &mut Map, x: usize, y: usize, tile_spec: u8, rotation: u8) {
+        map.tiles[y][x] = Some(PlacedTile {
+            tile_spec,
+            rotation,
+        });
+    }
+
+    fn of_kind(graph: &FeatureGraph, kind: FeatureKind) -> Vec<&MapFeature> {
+        graph
+            .features
+            .iter()
+            .filter(|feature| feature.kind == kind)
+            .collect()
+    }
+
+    #[test]
+    fn two_city_caps_make_one_complete_city() {
+        let mut cityn = tile("cityn", [City, Field, Field, Field]);
+        cityn.sheild = true;
+        let mut map = Map::new(vec![cityn], 2, 1);
+        // Turned to face each other across the middle of the map
+        place(&mut map, 0, 0, 0, 3);
+        place(&mut map, 1, 0, 0, 1);
+        let graph = find_features(&map);
+        let cities = of_kind(&graph, FeatureKind::City);
+        assert_eq!(cities.len(), 1);
+        assert!(cities[0].complete);
+        assert_eq!(cities[0].tiles, vec![(0, 0), (1, 0)]);
+        assert_eq!(cities[0].shields, 2);
+        let fields = of_kind(&graph, FeatureKind::Field);
+        assert_eq!(fields.len(), 2);
+        assert!(fields.iter().all(|field| !field.complete));
+        assert_eq!(
+            graph.feature_at(0, 0, Segment::E),
+            graph.feature_at(1, 0, Segment::W)
+        );
+        assert_eq!(graph.feature_at(0, 0, Segment::W), Some(1));
+    }
+
+    #[test]
+    fn a_road_off_the_map_is_open_at_both_ends() {
+        let mut map = Map::new(vec![tile("roadns", [Road, Field, Road, Field])], 1, 2);
+        place(&mut map, 0, 0, 0, 0);
+        place(&mut map, 0, 1, 0, 0);
+        let graph = find_features(&map);
+        let roads = of_kind(&graph, FeatureKind::Road);
+        assert_eq!(roads.len(), 1);
+        assert!(!roads[0].complete);
+        assert_eq!(
+            roads[0].open_edges,
+            vec![
+                OpenEdge {
+                    x: 0,
+                    y: 0,
+                    edge: 0
+                },
+                OpenEdge {
+                    x: 0,
+                    y: 1,
+                    edge: 2
+                }
+            ]
+        );
+        // The road divides the field on either side
+        assert_eq!(of_kind(&graph, FeatureKind::Field).len(), 2);
+    }
+
+    #[test]
+    fn a_surrounded_cloister_is_complete() {
+        let mut cloister = tile("cloister", [Field; 4]);
+        cloister.cloister = true;
+        let mut map = Map::new(vec![cloister], 3, 3);
+        for y in 0..3 {
+            for x in 0..3 {
+                place(&mut map, x, y, 0, 0);
+            }
+        }
+        let graph = find_features(&map);
+        assert_eq!(of_kind(&graph, FeatureKind::Cloister).len(), 9);
+        let centre = &graph.features[graph.cloister_at(1, 1).unwrap()];
+        assert!(centre.complete);
+        assert_eq!(centre.tiles.len(), 9);
+        assert_eq!(centre.tiles[0], (1, 1));
+        let corner = &graph.features[graph.cloister_at(0, 0).unwrap()];
+        assert!(!corner.complete);
+        assert_eq!(corner.tiles.len(), 4);
+        // The fields all join into one, running off every side
+        assert_eq!(of_kind(&graph, FeatureKind::Field).len(), 1);
+    }
+
+    #[test]
+    fn a_wrapping_road_closes_on_itself() {
+        let mut map =
+            Map::new(vec![tile("roadns", [Road, Field, Road, Field])], 1, 2).with_wrap(Wrap::Y);
+        place(&mut map, 0, 0, 0, 0);
+        place(&mut map, 0, 1, 0, 0);
+        let roads = of_kind(&find_features(&map), FeatureKind::Road)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(roads.len(), 1);
+        assert!(roads[0].complete);
+    }
+}
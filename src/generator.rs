@@ -0,0 +1,881 @@
+use log::info;
+
+use crate::constraints::Constraints;
+use crate::deck::Deck;
+use crate::density::Density;
+use crate::error::GenerateError;
+use crate::growth;
+use crate::layout::parse_pair;
+use crate::map::{BorderPolicy, Map, PlacedTile, Wrap};
+use crate::river;
+use crate::rng::Rng;
+use crate::shape::Shape;
+use crate::solver;
+use crate::tile::{Expansion, TileSpec};
+use crate::weights::Weights;
+use crate::wfc;
+
+/// How the cells left after laying the river are filled with base tiles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    /// Fill row by row from the bottom right, backtracking when stuck.
+    #[default]
+    Scanline,
+    /// Wave function collapse, filling the most constrained cell next.
+    Wfc,
+    /// Grow outwards from a start tile as a game is played, leaving the rest
+    /// of the map empty. Regenerated cells are filled row by row.
+    Organic,
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(Algorithm::Scanline),
+            "wfc" => Ok(Algorithm::Wfc),
+            "organic" => Ok(Algorithm::Organic),
+            _ => Err(format!(
+                "unknown algorithm {}, expected scanline, wfc or organic",
+                s
+            )),
+        }
+    }
+}
+
+/// How the tile placed in each cell is chosen from those which fit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sampling {
+    /// In proportion to each tile's count in the box. Tiles which fit in
+    /// many places end up more common than in the box.
+    #[default]
+    Weighted,
+    /// Steered towards each tile's share of the box, favouring tiles the map
+    /// has too few of so far.
+    Faithful,
+}
+
+impl std::str::FromStr for Sampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weighted" => Ok(Sampling::Weighted),
+            "faithful" => Ok(Sampling::Faithful),
+            _ => Err(format!(
+                "unknown sampling {}, expected weighted or faithful",
+                s
+            )),
+        }
+    }
+}
+
+/// Where the river ends when it rises from a source tile.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiverMouth {
+    /// The river flows into a lake tile without leaving the map.
+    #[default]
+    Lake,
+    /// The river flows off the edge of the map.
+    Border,
+}
+
+impl std::str::FromStr for RiverMouth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lake" => Ok(RiverMouth::Lake),
+            "border" => Ok(RiverMouth::Border),
+            _ => Err(format!(
+                "unknown river mouth {}, expected lake or border",
+                s
+            )),
+        }
+    }
+}
+
+/// The general direction the river flows across the map.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowDirection {
+    /// A direction chosen at random for each map.
+    #[default]
+    Any,
+    North,
+    East,
+    South,
+    West,
+}
+
+impl FlowDirection {
+    /// Returns the edge, north, east, south or west, the river flows towards.
+    pub fn edge(&self) -> Option<usize> {
+        match self {
+            FlowDirection::Any => None,
+            FlowDirection::North => Some(0),
+            FlowDirection::East => Some(1),
+            FlowDirection::South => Some(2),
+            FlowDirection::West => Some(3),
+        }
+    }
+}
+
+impl std::str::FromStr for FlowDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(FlowDirection::Any),
+            "north" => Ok(FlowDirection::North),
+            "east" => Ok(FlowDirection::East),
+            "south" => Ok(FlowDirection::South),
+            "west" => Ok(FlowDirection::West),
+            _ => Err(format!(
+                "unknown flow direction {}, expected any, north, east, south or west",
+                s
+            )),
+        }
+    }
+}
+
+/// Percentage chance the river turns aside at each tile when not configured.
+pub const DEFAULT_MEANDER: u32 = 30;
+
+/// Options controlling how the river is laid.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct RiverConfig {
+    pub mouth: RiverMouth,
+    /// Fewest tiles in the river, counting its source and lake. A river
+    /// ending in a lake defaults to the river tiles in one box or the length
+    /// of the map in the direction of flow, whichever is more, as far as a
+    /// finite deck has river tiles.
+    pub min_length: Option<u32>,
+    /// Most tiles in the river. A river ending in a lake defaults to its
+    /// minimum length, one flowing off the map to no limit.
+    pub max_length: Option<u32>,
+    pub direction: FlowDirection,
+    /// Percentage chance the river turns aside at each tile, and once turned
+    /// that it carries on sideways rather than turning back downstream.
+    pub meander: u32,
+    /// Let a river pass beside an earlier stretch of itself or another river.
+    pub self_adjacent: bool,
+    /// Number of rivers on the map.
+    pub count: u32,
+    /// Number of times the rivers fork, when the catalog has fork tiles.
+    /// Defaults to the fork tiles in one box.
+    pub forks: Option<u32>,
+    /// Let a river flow into another one, ending there, when the catalog has
+    /// fork tiles to join them.
+    pub merge: bool,
+}
+
+impl Default for RiverConfig {
+    fn default() -> Self {
+        RiverConfig {
+            mouth: RiverMouth::default(),
+            min_length: None,
+            max_length: None,
+            direction: FlowDirection::default(),
+            meander: DEFAULT_MEANDER,
+            self_adjacent: false,
+            count: 1,
+            forks: None,
+            merge: false,
+        }
+    }
+}
+
+/// Options controlling how a map is generated.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct GeneratorConfig {
+    pub algorithm: Algorithm,
+    pub sampling: Sampling,
+    /// Number of copies of the game the map is laid with, each tile being
+    /// used at most as many times as it comes in the boxes. Tiles are reused
+    /// freely when not set.
+    pub copies: Option<u32>,
+    /// With a limited number of copies, shrink the map until it can be laid
+    /// with the tiles available instead of failing.
+    pub fit_to_deck: bool,
+    /// Which features may run off the edge of the map.
+    pub border: BorderPolicy,
+    /// Which edges of the map join up with the opposite edge.
+    pub wrap: Wrap,
+    pub river: RiverConfig,
+    /// Tiles placed before anything else, which the rest of the map is laid
+    /// around.
+    pub pins: Vec<Pin>,
+    /// Which cells of the map are laid with tiles.
+    pub shape: Shape,
+    /// How likely each base game tile is to be chosen.
+    pub weights: Weights,
+    /// How the mix of tiles varies from one part of the map to another.
+    pub density: Density,
+    /// Properties the cities, roads and cloisters of the map should have,
+    /// repaired towards once it is laid.
+    pub constraints: Constraints,
+    /// Id of the tile an organic map grows from, the river's source or the
+    /// base game's start tile if not given.
+    pub start_tile: Option<String>,
+    /// Most tiles laid on an organic map. Defaults to the whole deck, or one
+    /// box's worth when tiles are reused freely.
+    pub tile_budget: Option<u32>,
+}
+
+/// A tile placed at a fixed position and rotation before the map is
+/// generated.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct Pin {
+    pub x: u32,
+    pub y: u32,
+    /// Id of the tile in the catalog.
+    pub tile: String,
+    /// Number of quarter turns anticlockwise, 0 to 3, so a city on the
+    /// north edge of the art ends up on the west edge after one turn.
+    #[serde(default)]
+    pub rotation: u8,
+}
+
+impl std::str::FromStr for Pin {
+    type Err = String;
+
+    /// Parses `X,Y,TILE` or `X,Y,TILE,ROTATION`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || format!("expected X,Y,TILE[,ROTATION], got {}", s);
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        let (x, y, tile, rotation) = match parts[..] {
+            [x, y, tile] => (x, y, tile, "0"),
+            [x, y, tile, rotation] => (x, y, tile, rotation),
+            _ => return Err(expected()),
+        };
+        Ok(Pin {
+            x: x.parse().map_err(|_| expected())?,
+            y: y.parse().map_err(|_| expected())?,
+            tile: tile.to_string(),
+            rotation: rotation.parse().map_err(|_| expected())?,
+        })
+    }
+}
+
+/// Cells of a map to regenerate.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+    /// A rectangle of cells, with its top left corner at (`x`, `y`).
+    Rect {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Any cells, each given as `[x, y]`.
+    Cells(Vec<(u32, u32)>),
+}
+
+impl Selection {
+    /// Returns the selected cells on the map row by row, each once.
+    pub fn cells(&self, map: &Map) -> Vec<(usize, usize)> {
+        let mut cells: Vec<(usize, usize)> = match self {
+            Selection::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => (*y..y.saturating_add(*height).min(map.size_y()))
+                .flat_map(|cy| {
+                    (*x..x.saturating_add(*width).min(map.size_x())).map(move |cx| (cx, cy))
+                })
+                .map(|(cx, cy)| (cx as usize, cy as usize))
+                .collect(),
+            Selection::Cells(cells) => cells
+                .iter()
+                .filter(|&&(x, y)| x < map.size_x() && y < map.size_y())
+                .map(|&(x, y)| (x as usize, y as usize))
+                .collect(),
+        };
+        cells.sort_by_key(|&(x, y)| (y, x));
+        cells.dedup();
+        cells
+    }
+}
+
+impl std::str::FromStr for Selection {
+    type Err = String;
+
+    /// Parses a rectangle given as `X,Y,WIDTHxHEIGHT`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || format!("expected X,Y,WIDTHxHEIGHT, got {}", s);
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        let [x, y, size] = parts[..] else {
+            return Err(expected());
+        };
+        let (width, height) = parse_pair(size).ok_or_else(expected)?;
+        Ok(Selection::Rect {
+            x: x.parse().map_err(|_| expected())?,
+            y: y.parse().map_err(|_| expected())?,
+            width,
+            height,
+        })
+    }
+}
+
+/// Generates a full map of `size_x` by `size_y` tiles, first laying the river
+/// and then filling the remaining cells with base game tiles. An organic map
+/// is grown from a start tile instead.
+///
+/// When the deck is limited to a number of copies of the game and
+/// `fit_to_deck` is set the map may be smaller than asked for, keeping
+/// roughly the same shape.
+pub fn generate_map(
+    specs: Vec<TileSpec>,
+    size_x: u32,
+    size_y: u32,
+    config: &GeneratorConfig,
+    rng: &mut Rng,
+) -> Result<Map, GenerateError> {
+    config.weights.check(&specs)?;
+    let mask = config.shape.mask(size_x, size_y, rng)?;
+    let Some(copies) = config.copies else {
+        let deck = sampled(Deck::unlimited(), &specs, config);
+        return generate_with_deck(specs, mask, config, deck, rng);
+    };
+    let deck = sampled(Deck::finite(&specs, copies), &specs, config);
+    let tiles = deck.size().unwrap_or_default();
+    // An organic map only grows as far as the deck goes
+    if config.algorithm == Algorithm::Organic {
+        return generate_with_deck(specs, mask, config, deck, rng);
+    }
+    if !config.fit_to_deck {
+        let cells = active_cells(&mask);
+        if cells > tiles {
+            return Err(GenerateError::DeckTooSmall { cells, tiles });
+        }
+        return generate_with_deck(specs, mask, config, deck, rng);
+    }
+
+    let (mut fit_x, mut fit_y) = (size_x, size_y);
+    let mut mask = mask;
+    while active_cells(&mask) > tiles {
+        (fit_x, fit_y) = shrink(fit_x, fit_y, size_x, size_y);
+        mask = config.shape.mask(fit_x, fit_y, rng)?;
+    }
+    loop {
+        match generate_with_deck(specs.clone(), mask, config, deck.clone(), rng) {
+            Ok(map) => return Ok(map),
+            Err(err) if fit_x <= 1 && fit_y <= 1 => return Err(err),
+            Err(err) => {
+                info!("Could not lay a {}x{} map: {}", fit_x, fit_y, err);
+                (fit_x, fit_y) = shrink(fit_x, fit_y, size_x, size_y);
+                mask = config.shape.mask(fit_x, fit_y, rng)?;
+            }
+        }
+    }
+}
+
+/// Sets the deck's targets when the tiles are sampled faithfully.
+fn sampled(deck: Deck, specs: &[TileSpec], config: &GeneratorConfig) -> Deck {
+    match config.sampling {
+        Sampling::Weighted => deck,
+        Sampling::Faithful => deck.with_targets(base_weights(specs, config)),
+    }
+}
+
+/// Returns the weight of each base game tile, the share of the map it should
+/// make up, with other tiles weighted 0.
+pub(crate) fn base_weights(specs: &[TileSpec], config: &GeneratorConfig) -> Vec<u32> {
+    let weights = config.weights.tile_weights(specs);
+    specs
+        .iter()
+        .zip(weights)
+        .map(|(spec, weight)| match spec.expansion {
+            Expansion::Base => weight,
+            _ => 0,
+        })
+        .collect()
+}
+
+/// How often a base game tile appears on a map compared to its share of the
+/// box, once weighted.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct TileFrequency {
+    pub id: String,
+    /// Percentage of the base game tiles this tile should make up.
+    pub target: f64,
+    /// Percentage of the base game tiles on the map which are this tile.
+    pub actual: f64,
+    /// Number of this tile on the map.
+    pub count: u32,
+}
+
+/// Returns the target and actual share of each base game tile on `map`.
+pub fn tile_frequencies(map: &Map, config: &GeneratorConfig) -> Vec<TileFrequency> {
+    let targets = base_weights(&map.specs, config);
+    let mut counts = vec![0u32; map.specs.len()];
+    for tile in map.tiles.iter().flatten().flatten() {
+        counts[tile.tile_spec as usize] += 1;
+    }
+    let percent = |n: u32, total: u32| match total {
+        0 => 0.0,
+        total => 100.0 * n as f64 / total as f64,
+    };
+    let target_total = targets.iter().sum();
+    let base: Vec<usize> = (0..map.specs.len())
+        .filter(|&tile| map.specs[tile].expansion == Expansion::Base)
+        .filter(|&tile| targets[tile] > 0 || counts[tile] > 0)
+        .collect();
+    let placed = base.iter().map(|&tile| counts[tile]).sum();
+    base.into_iter()
+        .map(|tile| TileFrequency {
+            id: map.specs[tile].id.clone(),
+            target: percent(targets[tile], target_total),
+            actual: percent(counts[tile], placed),
+            count: counts[tile],
+        })
+        .collect()
+}
+
+/// Returns the number of cells set in `mask`.
+fn active_cells(mask: &[Vec<bool>]) -> usize {
+    mask.iter().flatten().filter(|&&active| active).count()
+}
+
+/// Removes a column or a row, whichever keeps the map closest to the shape
+/// of a `size_x` by `size_y` map.
+fn shrink(x: u32, y: u32, size_x: u32, size_y: u32) -> (u32, u32) {
+    if y <= 1 || (x > 1 && x as u64 * size_y as u64 >= y as u64 * size_x as u64) {
+        (x - 1, y)
+    } else {
+        (x, y - 1)
+    }
+}
+
+/// Generates a map with the cells set in `mask`, drawing tiles from `deck`.
+fn generate_with_deck(
+    specs: Vec<TileSpec>,
+    mask: Vec<Vec<bool>>,
+    config: &GeneratorConfig,
+    mut deck: Deck,
+    rng: &mut Rng,
+) -> Result<Map, GenerateError> {
+    let size_y = mask.len() as u32;
+    let size_x = mask.first().map_or(0, |row| row.len()) as u32;
+    let mut map = Map::new(specs, size_x, size_y)
+        .with_border(config.border)
+        .with_wrap(config.wrap)
+        .with_mask(mask)
+        .with_density(config.density.bias(size_x, size_y, rng)?);
+    info!("Map created with size: {}x{}", map.size_x(), map.size_y());
+    place_pins(&mut map, &config.pins, &mut deck)?;
+    if config.algorithm == Algorithm::Organic {
+        growth::grow(&mut map, &mut deck, config, rng)?;
+        config.constraints.repair(&mut map, config, rng);
+        return Ok(map);
+    }
+
+    if !build_draw_deck(&map, Expansion::River).is_empty() {
+        let candidates = solver::weighted_candidates(&map, &base_weights(&map.specs, config));
+        river::place_river_tiles(&mut map, &mut deck, &config.river, &candidates, rng)?;
+        info!("River tiles placed successfully");
+    }
+
+    place_remaining_tiles(&mut map, config, &mut deck, rng)
+        .map_err(|err| exhausted_or(&map, &deck, err))?;
+    config.constraints.repair(&mut map, config, rng);
+    Ok(map)
+}
+
+/// Returns the error to report when the map could not be filled, running out
+/// of base tiles being reported over the search giving up.
+fn exhausted_or(map: &Map, deck: &Deck, err: GenerateError) -> GenerateError {
+    let tiles: Vec<String> = deck
+        .exhausted()
+        .into_iter()
+        .map(|tile| &map.specs[tile as usize])
+        .filter(|spec| spec.expansion == Expansion::Base)
+        .map(|spec| spec.id.clone())
+        .collect();
+    match tiles.is_empty() {
+        true => err,
+        false => GenerateError::DeckExhausted { tiles },
+    }
+}
+
+/// Clears the base game tiles in `selection` and lays them again, matching
+/// the tiles kept around them. River and pinned tiles are kept, and the map
+/// is left as it was if the selection cannot be laid again.
+///
+/// With a limited number of copies the tiles kept on the map are not drawn
+/// again.
+pub fn regenerate(
+    map: &mut Map,
+    selection: &Selection,
+    config: &GeneratorConfig,
+    rng: &mut Rng,
+) -> Result<(), GenerateError> {
+    config.weights.check(&map.specs)?;
+    let cells = replaceable(map, selection.cells(map));
+    info!("Regenerating {} cells", cells.len());
+    refill(map, cells, config, rng)
+}
+
+/// Returns the `cells` holding base game tiles which are not pinned, the
+/// tiles which may be laid again.
+pub(crate) fn replaceable(map: &Map, cells: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    cells
+        .into_iter()
+        .filter(|&(x, y)| {
+            !map.is_pinned(x, y)
+                && map.tile(x, y).is_some_and(|tile| {
+                    map.specs[tile.tile_spec as usize].expansion == Expansion::Base
+                })
+        })
+        .collect()
+}
+
+/// Clears `cells`, given row by row, and lays them again with tiles from a
+/// deck missing the tiles kept on the map. On failure the cells are put back
+/// as they were.
+pub(crate) fn refill(
+    map: &mut Map,
+    cells: Vec<(usize, usize)>,
+    config: &GeneratorConfig,
+    rng: &mut Rng,
+) -> Result<(), GenerateError> {
+    let kept = map.tiles.clone();
+    for &(x, y) in &cells {
+        map.tiles[y][x] = None;
+    }
+
+    let deck = match config.copies {
+        Some(copies) => Deck::finite(&map.specs, copies),
+        None => Deck::unlimited(),
+    };
+    let mut deck = sampled(deck, &map.specs, config);
+    for row in &map.tiles {
+        for tile in row.iter().flatten() {
+            if deck.has(tile.tile_spec) {
+                deck.take(tile.tile_spec);
+            }
+        }
+    }
+    fill_cells(map, config, &mut deck, cells, rng).map_err(|err| {
+        let err = exhausted_or(map, &deck, err);
+        map.tiles = kept;
+        err
+    })
+}
+
+/// Places the pinned tiles, drawing them from `deck`.
+fn place_pins(map: &mut Map, pins: &[Pin], deck: &mut Deck) -> Result<(), GenerateError> {
+    for pin in pins {
+        let Some(tile) = map.specs.iter().position(|spec| spec.id == pin.tile) else {
+            return Err(GenerateError::UnknownTile {
+                id: pin.tile.clone(),
+            });
+        };
+        let tile = tile as u8;
+        let (x, y) = (pin.x as usize, pin.y as usize);
+        if !map.is_valid_position(pin.x as i32, pin.y as i32) || pin.rotation > 3 {
+            return Err(GenerateError::InvalidPin { x: pin.x, y: pin.y });
+        }
+        if map.tile(x, y).is_some() || !map.can_be_placed(tile, x, y, pin.rotation) {
+            return Err(GenerateError::PinConflict { x: pin.x, y: pin.y });
+        }
+        if !deck.has(tile) {
+            return Err(GenerateError::DeckExhausted {
+                tiles: vec![pin.tile.clone()],
+            });
+        }
+        deck.take(tile);
+        map.pin(
+            x,
+            y,
+            PlacedTile {
+                tile_spec: tile,
+                rotation: pin.rotation,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Fills every empty cell with base game tiles from `deck` using the
+/// configured algorithm, so no gaps are left.
+pub fn place_remaining_tiles(
+    map: &mut Map,
+    config: &GeneratorConfig,
+    deck: &mut Deck,
+    rng: &mut Rng,
+) -> Result<(), GenerateError> {
+    let remaining = empty_cells(map);
+    info!("Remaining tiles to place: {}", remaining.len());
+    fill_cells(map, config, deck, remaining, rng)
+}
+
+/// Fills the empty `cells`, given row by row, with base game tiles from
+/// `deck` using the configured algorithm.
+fn fill_cells(
+    map: &mut Map,
+    config: &GeneratorConfig,
+    deck: &mut Deck,
+    mut remaining: Vec<(usize, usize)>,
+    rng: &mut Rng,
+) -> Result<(), GenerateError> {
+    let candidates = solver::weighted_candidates(map, &base_weights(&map.specs, config));
+    match config.algorithm {
+        Algorithm::Scanline | Algorithm::Organic => {
+            // Fill from the bottom right corner back towards the top left
+            remaining.reverse();
+            solver::fill(map, &candidates, deck, &remaining, rng)
+        }
+        Algorithm::Wfc => wfc::fill(map, &candidates, deck, &remaining, rng),
+    }
+}
+
+/// Returns the position of every cell without a tile, row by row.
+pub(crate) fn empty_cells(map: &Map) -> Vec<(usize, usize)> {
+    let mut cells = Vec::new();
+    for y in 0..map.size_y() {
+        for x in 0..map.size_x() {
+            if map.has_no_tile(x as i32, y as i32) {
+                cells.push((x as usize, y as usize));
+            }
+        }
+    }
+    cells
+}
+
+pub(crate) fn build_draw_deck(map: &Map, expansion: Expansion) -> Vec<u8> {
+    let mut deck = Vec::new();
+    for (i, tile_spec) in map.specs.iter().enumerate() {
+        if tile_spec.expansion != expansion {
+            continue;
+        }
+        for _ in 0..tile_spec.count {
+            deck.push(i as u8);
+        }
+    }
+    deck
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::{edges_match, highres};
+    use crate::tile::Feature;
+
+    fn generate(config: &GeneratorConfig, seed: u64) -> Result<Map, GenerateError> {
+        generate_map(highres(), 12, 9, config, &mut Rng::new(seed))
+    }
+
+    fn algorithm(algorithm: Algorithm) -> GeneratorConfig {
+        GeneratorConfig {
+            algorithm,
+            ..GeneratorConfig::default()
+        }
+    }
+
+    fn count(map: &Map, expansion: Expansion) -> usize {
+        map.tiles
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|tile| map.specs[tile.tile_spec as usize].expansion == expansion)
+            .count()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_map() {
+        for config in [
+            algorithm(Algorithm::Scanline),
+            algorithm(Algorithm::Wfc),
+            algorithm(Algorithm::Organic),
+        ] {
+            let a = generate(&config, 42).unwrap();
+            let b = generate(&config, 42).unwrap();
+            assert_eq!(a.tiles, b.tiles);
+            let c = generate(&config, 43).unwrap();
+            assert_ne!(a.tiles, c.tiles);
+        }
+    }
+
+    #[test]
+    fn fills_the_map_around_the_river() {
+        for mut config in [algorithm(Algorithm::Scanline), algorithm(Algorithm::Wfc)] {
+            let map = generate(&config, 7).unwrap();
+            assert!(map.tiles.iter().flatten().all(Option::is_some));
+            assert!(edges_match(&map));
+            assert!((10..=12).contains(&count(&map, Expansion::River)));
+            // The map is longer east to west than a box of river tiles
+            config.river.direction = FlowDirection::East;
+            let map = generate(&config, 7).unwrap();
+            assert_eq!(count(&map, Expansion::River), 12);
+        }
+    }
+
+    #[test]
+    fn a_closed_wrapping_map_matches_everywhere() {
+        let config = GeneratorConfig {
+            border: BorderPolicy::Closed,
+            wrap: Wrap::X,
+            ..GeneratorConfig::default()
+        };
+        let map = generate(&config, 3).unwrap();
+        assert!(edges_match(&map));
+    }
+
+    #[test]
+    fn a_small_deck_is_rejected_or_fitted() {
+        let config = GeneratorConfig {
+            copies: Some(1),
+            ..GeneratorConfig::default()
+        };
+        let result = generate_map(highres(), 20, 20, &config, &mut Rng::new(1));
+        assert_eq!(
+            result.err(),
+            Some(GenerateError::DeckTooSmall {
+                cells: 400,
+                tiles: 81
+            })
+        );
+
+        let config = GeneratorConfig {
+            fit_to_deck: true,
+            ..config
+        };
+        let map = generate_map(highres(), 20, 20, &config, &mut Rng::new(1)).unwrap();
+        assert!(map.size_x() * map.size_y() <= 81);
+        assert!(edges_match(&map));
+        let mut used = vec![0; map.specs.len()];
+        for tile in map.tiles.iter().flatten().flatten() {
+            used[tile.tile_spec as usize] += 1;
+        }
+        assert!(map
+            .specs
+            .iter()
+            .zip(used)
+            .all(|(spec, used)| used <= spec.count));
+    }
+
+    #[test]
+    fn pinned_tiles_stay_put() {
+        let config = GeneratorConfig {
+            pins: vec!["3,4,citynesw_shield".parse().unwrap()],
+            ..GeneratorConfig::default()
+        };
+        let map = generate(&config, 5).unwrap();
+        let pinned = map.tile(3, 4).unwrap();
+        assert_eq!(map.specs[pinned.tile_spec as usize].id, "citynesw_shield");
+        assert!(map.is_pinned(3, 4));
+        assert!(edges_match(&map));
+    }
+
+    #[test]
+    fn pins_turn_anticlockwise() {
+        let config = GeneratorConfig {
+            pins: vec!["3,4,cityn,1".parse().unwrap()],
+            ..GeneratorConfig::default()
+        };
+        let map = generate(&config, 5).unwrap();
+        let pinned = map.tile(3, 4).unwrap();
+        let spec = &map.specs[pinned.tile_spec as usize];
+        let edges: Vec<Feature> = (0..4).map(|d| spec.edge(d, pinned.rotation)).collect();
+        assert_eq!(
+            edges,
+            vec![Feature::None, Feature::None, Feature::None, Feature::City]
+        );
+    }
+
+    #[test]
+    fn bad_pins_are_rejected() {
+        let pinned = |pin: &str| GeneratorConfig {
+            pins: vec![pin.parse().unwrap()],
+            ..GeneratorConfig::default()
+        };
+        assert_eq!(
+            generate(&pinned("12,0,cityn"), 0).err(),
+            Some(GenerateError::InvalidPin { x: 12, y: 0 })
+        );
+        assert_eq!(
+            generate(&pinned("1,1,cityn,4"), 0).err(),
+            Some(GenerateError::InvalidPin { x: 1, y: 1 })
+        );
+        assert_eq!(
+            generate(&pinned("1,1,castle"), 0).err(),
+            Some(GenerateError::UnknownTile {
+                id: "castle".to_string()
+            })
+        );
+        assert!("1,1".parse::<Pin>().is_err());
+    }
+
+    #[test]
+    fn regenerating_keeps_the_tiles_outside_the_selection() {
+        let config = GeneratorConfig::default();
+        let mut map = generate(&config, 11).unwrap();
+        let before = map.tiles.clone();
+        let selection: Selection = "2,2,4x3".parse().unwrap();
+        let selected = selection.cells(&map);
+        assert_eq!(selected.len(), 12);
+        regenerate(&mut map, &selection, &config, &mut Rng::new(12)).unwrap();
+        assert!(edges_match(&map));
+        for (y, row) in before.iter().enumerate() {
+            for (x, &tile) in row.iter().enumerate() {
+                assert!(map.tiles[y][x].is_some());
+                if !selected.contains(&(x, y)) {
+                    assert_eq!(map.tiles[y][x], tile);
+                }
+            }
+        }
+        assert!(Selection::Cells(vec![(0, 0), (99, 0)]).cells(&map) == vec![(0, 0)]);
+    }
+
+    #[test]
+    fn a_failed_regeneration_leaves_the_map_as_it_was() {
+        let mut map = generate(&GeneratorConfig::default(), 11).unwrap();
+        let before = map.tiles.clone();
+        // The tiles kept already use up more than one box
+        let config = GeneratorConfig {
+            copies: Some(1),
+            ..GeneratorConfig::default()
+        };
+        let selection: Selection = "2,2,4x3".parse().unwrap();
+        let result = regenerate(&mut map, &selection, &config, &mut Rng::new(12));
+        assert!(matches!(result, Err(GenerateError::DeckExhausted { .. })));
+        assert!(map.tiles == before);
+    }
+
+    #[test]
+    fn tile_frequencies_add_up() {
+        let config = GeneratorConfig::default();
+        let map = generate(&config, 2).unwrap();
+        let frequencies = tile_frequencies(&map, &config);
+        let total = |share: fn(&TileFrequency) -> f64| frequencies.iter().map(share).sum::<f64>();
+        assert!((total(|tile| tile.actual) - 100.0).abs() < 1e-6);
+        assert!((total(|tile| tile.target) - 100.0).abs() < 1e-6);
+        let counted: u32 = frequencies.iter().map(|tile| tile.count).sum();
+        assert_eq!(counted as usize, count(&map, Expansion::Base));
+    }
+
+    #[test]
+    fn shrinking_keeps_the_shape() {
+        assert_eq!(shrink(40, 30, 40, 30), (39, 30));
+        assert_eq!(shrink(39, 30, 40, 30), (39, 29));
+        assert_eq!(shrink(1, 5, 40, 30), (1, 4));
+    }
+
+    #[test]
+    fn parses_generator_options() {
+        assert_eq!("wfc".parse(), Ok(Algorithm::Wfc));
+        assert_eq!("faithful".parse(), Ok(Sampling::Faithful));
+        assert_eq!("border".parse(), Ok(RiverMouth::Border));
+        assert!("spiral".parse::<Algorithm>().is_err());
+    }
+}
@@ -0,0 +1,486 @@
+//! Organic growth, laying the map as a game would be played.
+//!
+//! The map starts from a single tile near the centre. Tiles are then drawn
+//! one at a time and placed at a random spot and rotation where they fit
+//! beside the tiles already laid, so the map grows outwards with a ragged
+//! edge and leaves the rest of the rectangle empty. As in the game, the river
+//! is played first from its source and a tile which fits nowhere is
+//! discarded.
+
+use log::info;
+
+use crate::deck::Deck;
+use crate::density;
+use crate::error::GenerateError;
+use crate::generator::{base_weights, build_draw_deck, GeneratorConfig};
+use crate::map::{Map, PlacedTile};
+use crate::rng::Rng;
+use crate::tile::{Expansion, Feature, RiverEnd};
+
+/// Each more tile beside a cell makes placing a tile there this many powers
+/// of two more likely.
+const NEIGHBOUR_WEIGHT: usize = 2;
+
+/// Id of the base game's start tile, a city with a road running beside it.
+pub const DEFAULT_START_TILE: &str = "cityn_roadew";
+
+/// Grows a map from the configured start tile, or the river's source or the
+/// base game's start tile when not given, drawing tiles from `deck` until the
+/// tile budget is laid, the deck runs out or there is nowhere left to grow.
+/// An unlimited deck lays one box's worth of tiles when there is no budget,
+/// drawing base game tiles by their weights.
+pub fn grow(
+    map: &mut Map,
+    deck: &mut Deck,
+    config: &GeneratorConfig,
+    rng: &mut Rng,
+) -> Result<(), GenerateError> {
+    let river_tiles = build_draw_deck(map, Expansion::River);
+    let source = river_tiles
+        .iter()
+        .copied()
+        .find(|&tile| map.specs()[tile as usize].river_end == Some(RiverEnd::Source));
+    let start = match config.start_tile.as_deref() {
+        Some(id) => Some(find_tile(map, id)?),
+        None => source.or_else(|| find_tile(map, DEFAULT_START_TILE).ok()),
+    };
+    let Some(start) = start.or_else(|| build_draw_deck(map, Expansion::Base).first().copied())
+    else {
+        return Ok(());
+    };
+    if !deck.has(start) {
+        return Err(GenerateError::DeckExhausted {
+            tiles: vec![map.specs()[start as usize].id.clone()],
+        });
+    }
+
+    // Played in order, the river from its source to the lake before the rest
+    let mut river: Vec<u8> = river_tiles
+        .into_iter()
+        .filter(|&tile| Some(tile) != source)
+        .collect();
+    shuffle(&mut river, rng);
+    river.sort_by_key(|&tile| map.specs()[tile as usize].river_end == Some(RiverEnd::Lake));
+    let base = build_draw_deck(map, Expansion::Base);
+    let mut pile = match deck.size() {
+        Some(_) => {
+            let mut pile: Vec<u8> = (0..map.specs().len() as u8)
+                .filter(|&tile| map.specs()[tile as usize].expansion == Expansion::Base)
+                .flat_map(|tile| std::iter::repeat_n(tile, deck.count(tile) as usize))
+                .collect();
+            shuffle(&mut pile, rng);
+            pile
+        }
+        None => Vec::new(),
+    };
+    let weights = base_weights(map.specs(), config);
+    let total: usize = weights.iter().map(|&weight| weight as usize).sum();
+    let budget = match (config.tile_budget, deck.size()) {
+        (Some(budget), _) => budget as usize,
+        (None, Some(size)) => size,
+        (None, None) => river.len() + 1 + base.len(),
+    };
+
+    let Some((x, y, rotation)) = start_cell(map, start, rng) else {
+        let (x, y) = (map.size_x() as usize / 2, map.size_y() as usize / 2);
+        return Err(GenerateError::NoTileFits { x, y });
+    };
+    let mut frontier = Frontier::new(map);
+    place(map, deck, &mut frontier, start, x, y, rotation);
+    let mut laid = 1;
+    let mut discarded = 0;
+    river.reverse();
+    while laid < budget && !frontier.cells.is_empty() {
+        let tile = match (river.pop(), deck.size()) {
+            (Some(tile), _) => tile,
+            (None, Some(_)) => match pile.pop() {
+                Some(tile) => tile,
+                None => break,
+            },
+            (None, None) if total > 0 => {
+                let mut target = rng.below(total);
+                let tile = weights
+                    .iter()
+                    .position(|&weight| match target < weight as usize {
+                        true => true,
+                        false => {
+                            target -= weight as usize;
+                            false
+                        }
+                    })
+                    .expect("target is always below the total weight");
+                tile as u8
+            }
+            (None, None) => break,
+        };
+        if !deck.has(tile) {
+            continue;
+        }
+        let spots = spots(map, &frontier, tile);
+        if spots.is_empty() {
+            discarded += 1;
+            // An unlimited deck never runs out, so stop growing where no
+            // tile it draws will ever fit
+            if deck.size().is_none() && river.is_empty() {
+                frontier.retain_fillable(map, &weights);
+            }
+            continue;
+        }
+        let total = spots.iter().map(|spot| spot.3).sum();
+        let mut target = rng.below_u64(total);
+        let &(x, y, rotation, _) = spots
+            .iter()
+            .find(|spot| match target < spot.3 {
+                true => true,
+                false => {
+                    target -= spot.3;
+                    false
+                }
+            })
+            .expect("target is always below the total weight");
+        place(map, deck, &mut frontier, tile, x, y, rotation);
+        laid += 1;
+    }
+    info!("Grew the map to {} tiles, discarding {}", laid, discarded);
+    centre(map);
+    Ok(())
+}
+
+/// Returns the tile with the given id.
+fn find_tile(map: &Map, id: &str) -> Result<u8, GenerateError> {
+    map.specs()
+        .iter()
+        .position(|spec| spec.id == id)
+        .map(|tile| tile as u8)
+        .ok_or_else(|| GenerateError::UnknownTile { id: id.to_string() })
+}
+
+/// Returns the empty cell closest to the centre of the map where the start
+/// tile fits, turned at random.
+fn start_cell(map: &Map, tile: u8, rng: &mut Rng) -> Option<(usize, usize, u8)> {
+    let (size_x, size_y) = (map.size_x() as usize, map.size_y() as usize);
+    let rotation = match map.specs()[tile as usize].can_be_rotated() {
+        true => rng.below(4) as u8,
+        false => 0,
+    };
+    let mut cells: Vec<(usize, usize)> = (0..size_y)
+        .flat_map(|y| (0..size_x).map(move |x| (x, y)))
+        .collect();
+    cells.sort_by_key(|&(x, y)| {
+        (2 * x).abs_diff(size_x - 1).pow(2) + (2 * y).abs_diff(size_y - 1).pow(2)
+    });
+    cells
+        .into_iter()
+        .filter(|&(x, y)| map.has_no_tile(x as i32, y as i32))
+        .find_map(|(x, y)| {
+            (0..4)
+                .map(|turn| (rotation + turn) % 4)
+                .find(|&rotation| map.can_be_placed(tile, x, y, rotation))
+                .map(|rotation| (x, y, rotation))
+        })
+}
+
+/// The empty cells beside the tiles laid so far.
+struct Frontier {
+    cells: Vec<(usize, usize)>,
+    /// Whether each cell, row by row, is in `cells`.
+    contains: Vec<bool>,
+    size_x: usize,
+}
+
+impl Frontier {
+    /// Starts with the cells beside any tiles already on the map.
+    fn new(map: &Map) -> Self {
+        let size_x = map.size_x() as usize;
+        let mut frontier = Frontier {
+            cells: Vec::new(),
+            contains: vec![false; size_x * map.size_y() as usize],
+            size_x,
+        };
+        for y in 0..map.size_y() as usize {
+            for x in 0..size_x {
+                if map.tile(x, y).is_some() {
+                    frontier.grow_from(map, x, y);
+                }
+            }
+        }
+        frontier
+    }
+
+    /// Adds the empty cells beside (`x`, `y`).
+    fn grow_from(&mut self, map: &Map, x: usize, y: usize) {
+        for edge in 0..4 {
+            if let Some((nx, ny)) = map.neighbour(x, y, edge) {
+                if map.tile(nx, ny).is_none() && !self.contains[ny * self.size_x + nx] {
+                    self.contains[ny * self.size_x + nx] = true;
+                    self.cells.push((nx, ny));
+                }
+            }
+        }
+    }
+
+    /// Drops the cells where no tile with a weight fits. Tiles laid beside a
+    /// cell only narrow what fits there, so such a cell can never be filled.
+    fn retain_fillable(&mut self, map: &Map, weights: &[u32]) {
+        let contains = &mut self.contains;
+        let size_x = self.size_x;
+        self.cells.retain(|&(x, y)| {
+            let fillable = (0..weights.len())
+                .filter(|&tile| weights[tile] > 0)
+                .any(|tile| (0..4).any(|rotation| map.can_be_placed(tile as u8, x, y, rotation)));
+            contains[y * size_x + x] = fillable;
+            fillable
+        });
+    }
+
+    fn remove(&mut self, x: usize, y: usize) {
+        if std::mem::take(&mut self.contains[y * self.size_x + x]) {
+            self.cells.retain(|&cell| cell != (x, y));
+        }
+    }
+}
+
+/// Returns every cell and rotation on the frontier where `tile` fits, with
+/// the weight it is chosen by, raised where the tile suits the density of the
+/// cell. A river tile must also carry on the river from a tile already laid.
+fn spots(map: &Map, frontier: &Frontier, tile: u8) -> Vec<(usize, usize, u8, u64)> {
+    let spec = &map.specs()[tile as usize];
+    let rotations = match spec.can_be_rotated() {
+        true => 4,
+        false => 1,
+    };
+    let mut spots = Vec::new();
+    for &(x, y) in &frontier.cells {
+        let neighbours = (0..4)
+            .filter(|&edge| {
+                map.neighbour(x, y, edge)
+                    .is_some_and(|(nx, ny)| map.tile(nx, ny).is_some())
+            })
+            .count();
+        // Like a player, prefer to fill in the gaps between tiles so the map
+        // stays close knit
+        let weight = density::biased(
+            map,
+            1 << (NEIGHBOUR_WEIGHT * (neighbours.max(1) - 1)),
+            tile,
+            x,
+            y,
+        );
+        for rotation in 0..rotations {
+            let continues_river = (0..4).any(|edge| {
+                spec.edge(edge, rotation) == Feature::River
+                    && map
+                        .neighbour(x, y, edge)
+                        .is_some_and(|(nx, ny)| map.tile(nx, ny).is_some())
+            });
+            if (spec.expansion != Expansion::River || continues_river)
+                && map.can_be_placed(tile, x, y, rotation)
+            {
+                spots.push((x, y, rotation, weight));
+            }
+        }
+    }
+    spots
+}
+
+fn place(
+    map: &mut Map,
+    deck: &mut Deck,
+    frontier: &mut Frontier,
+    tile: u8,
+    x: usize,
+    y: usize,
+    rotation: u8,
+) {
+    map.tiles[y][x] = Some(PlacedTile {
+        tile_spec: tile,
+        rotation,
+    });
+    deck.take(tile);
+    frontier.remove(x, y);
+    frontier.grow_from(map, x, y);
+}
+
+/// Moves the tiles so they sit in the middle of the map, unless the map
+/// wraps, has a shape, a density field or pinned tiles which must stay where
+/// they are.
+fn centre(map: &mut Map) {
+    let (size_x, size_y) = (map.size_x() as usize, map.size_y() as usize);
+    let cells: Vec<(usize, usize)> = (0..size_y)
+        .flat_map(|y| (0..size_x).map(move |x| (x, y)))
+        .collect();
+    let fixed = map.wrap().x()
+        || map.wrap().y()
+        || cells.iter().any(|&(x, y)| {
+            !map.is_active(x, y) || map.is_pinned(x, y) || map.density(x, y).is_some()
+        });
+    let laid: Vec<(usize, usize)> = cells
+        .into_iter()
+        .filter(|&(x, y)| map.tile(x, y).is_some())
+        .collect();
+    if fixed || laid.is_empty() {
+        return;
+    }
+    let min_x = laid.iter().map(|&(x, _)| x).min().unwrap_or_default();
+    let max_x = laid.iter().map(|&(x, _)| x).max().unwrap_or_default();
+    let min_y = laid.iter().map(|&(_, y)| y).min().unwrap_or_default();
+    let max_y = laid.iter().map(|&(_, y)| y).max().unwrap_or_default();
+    let to_x = (size_x - (max_x - min_x + 1)) / 2;
+    let to_y = (size_y - (max_y - min_y + 1)) / 2;
+    let mut tiles = vec![vec![None; size_x]; size_y];
+    for (x, y) in laid {
+        tiles[y - min_y + to_y][x - min_x + to_x] = map.tiles[y][x].take();
+    }
+    map.tiles = tiles;
+}
+
+/// Shuffles `tiles` into a random order.
+fn shuffle(tiles: &mut [u8], rng: &mut Rng) {
+    for i in (1..tiles.len()).rev() {
+        tiles.swap(i, rng.below(i + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::{base, edges_match, highres};
+    use crate::tile::TileSpec;
+
+    fn organic(tile_budget: Option<u32>) -> GeneratorConfig {
+        GeneratorConfig {
+            algorithm: crate::generator::Algorithm::Organic,
+            tile_budget,
+            ..GeneratorConfig::default()
+        }
+    }
+
+    fn laid(map: &Map) -> Vec<(usize, usize)> {
+        (0..map.size_y() as usize)
+            .flat_map(|y| (0..map.size_x() as usize).map(move |x| (x, y)))
+            .filter(|&(x, y)| map.tile(x, y).is_some())
+            .collect()
+    }
+
+    /// Returns true if every tile laid can be reached from the first.
+    fn connected(map: &Map) -> bool {
+        let laid = laid(map);
+        let mut reached = vec![laid[0]];
+        let mut stack = vec![laid[0]];
+        while let Some((x, y)) = stack.pop() {
+            for edge in 0..4 {
+                if let Some(cell) = map.neighbour(x, y, edge) {
+                    if map.tile(cell.0, cell.1).is_some() && !reached.contains(&cell) {
+                        reached.push(cell);
+                        stack.push(cell);
+                    }
+                }
+            }
+        }
+        reached.len() == laid.len()
+    }
+
+    fn grown(specs: Vec<TileSpec>, config: &GeneratorConfig, seed: u64) -> Map {
+        let mut map = Map::new(specs, 20, 16);
+        grow(
+            &mut map,
+            &mut Deck::unlimited(),
+            config,
+            &mut Rng::new(seed),
+        )
+        .unwrap();
+        map
+    }
+
+    #[test]
+    fn grows_to_the_budget_in_one_piece() {
+        let map = grown(highres(), &organic(Some(30)), 1);
+        assert_eq!(laid(&map).len(), 30);
+        assert!(connected(&map));
+        assert!(edges_match(&map));
+    }
+
+    #[test]
+    fn plays_the_river_first_from_its_source() {
+        let map = grown(highres(), &organic(Some(12)), 2);
+        let river = laid(&map)
+            .into_iter()
+            .map(|(x, y)| &map.specs()[map.tile(x, y).unwrap().tile_spec as usize])
+            .filter(|spec| spec.expansion == Expansion::River)
+            .count();
+        assert_eq!(river, 10);
+    }
+
+    #[test]
+    fn starts_from_the_start_tile_without_a_river() {
+        let map = grown(base(), &organic(Some(1)), 3);
+        let tiles = laid(&map);
+        assert_eq!(tiles.len(), 1);
+        let (x, y) = tiles[0];
+        assert_eq!(
+            map.specs()[map.tile(x, y).unwrap().tile_spec as usize].id,
+            DEFAULT_START_TILE
+        );
+        // Centred on the map
+        assert_eq!((x, y), (9, 7));
+    }
+
+    #[test]
+    fn a_finite_deck_bounds_the_growth() {
+        let mut map = Map::new(base(), 20, 16);
+        let mut deck = Deck::finite(map.specs(), 1);
+        grow(&mut map, &mut deck, &organic(None), &mut Rng::new(4)).unwrap();
+        assert!(laid(&map).len() <= 71);
+        assert!(connected(&map));
+    }
+
+    #[test]
+    fn stops_where_nothing_drawn_can_fit() {
+        // Only cloisters are drawn, which never fit beside a city or road
+        let config = GeneratorConfig {
+            weights: crate::weights::Weights {
+                tiles: base()
+                    .into_iter()
+                    .filter(|spec| spec.id != "cloister")
+                    .map(|spec| (spec.id, 0))
+                    .collect(),
+                ..crate::weights::Weights::default()
+            },
+            ..organic(None)
+        };
+        let mut map = Map::new(base(), 8, 8);
+        grow(&mut map, &mut Deck::unlimited(), &config, &mut Rng::new(6)).unwrap();
+        let tiles = laid(&map);
+        assert!(tiles.len() > 1 && tiles.len() < 64);
+        assert!(edges_match(&map));
+
+        // Nothing closes off every edge of a small closed map
+        let config = GeneratorConfig {
+            border: crate::map::BorderPolicy::Closed,
+            river: crate::generator::RiverConfig {
+                count: 0,
+                ..crate::generator::RiverConfig::default()
+            },
+            ..organic(None)
+        };
+        let map =
+            crate::generator::generate_map(highres(), 3, 3, &config, &mut Rng::new(3)).unwrap();
+        assert!(edges_match(&map));
+    }
+
+    #[test]
+    fn an_unknown_start_tile_is_rejected() {
+        let config = GeneratorConfig {
+            start_tile: Some("castle".to_string()),
+            ..organic(None)
+        };
+        let mut map = Map::new(base(), 5, 5);
+        let result = grow(&mut map, &mut Deck::unlimited(), &config, &mut Rng::new(5));
+        assert_eq!(
+            result,
+            Err(GenerateError::UnknownTile {
+                id: "castle".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,395 @@
+use crate::map::Wrap;
+
+/// Default size in pixels each tile is drawn at.
+pub const DEFAULT_TILE_SIZE: u32 = 128;
+
+/// Default printing resolution.
+pub const DEFAULT_DPI: u32 = 600;
+
+/// Default physical size of a tile when printing, chosen so tiles are drawn
+/// at `DEFAULT_TILE_SIZE` pixels at `DEFAULT_DPI`.
+pub const DEFAULT_TILE_MM: f64 = DEFAULT_TILE_SIZE as f64 * MM_PER_INCH / DEFAULT_DPI as f64;
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// Standard paper sizes, or a custom size in millimetres.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaperSize {
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    Letter,
+    Tabloid,
+    Custom { width_mm: f64, height_mm: f64 },
+}
+
+impl PaperSize {
+    /// Returns the width and height in millimetres in portrait orientation.
+    pub fn size_mm(&self) -> (f64, f64) {
+        let (width, height) = match *self {
+            PaperSize::A0 => (841.0, 1189.0),
+            PaperSize::A1 => (594.0, 841.0),
+            PaperSize::A2 => (420.0, 594.0),
+            PaperSize::A3 => (297.0, 420.0),
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::A5 => (148.0, 210.0),
+            PaperSize::Letter => (215.9, 279.4),
+            PaperSize::Tabloid => (279.4, 431.8),
+            PaperSize::Custom {
+                width_mm,
+                height_mm,
+            } => (width_mm, height_mm),
+        };
+        (width.min(height), width.max(height))
+    }
+}
+
+impl std::str::FromStr for PaperSize {
+    type Err = String;
+
+    /// Parses a paper name such as `a4` or `letter`, or a custom size in
+    /// millimetres written as `WIDTHxHEIGHT`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "a0" => Ok(PaperSize::A0),
+            "a1" => Ok(PaperSize::A1),
+            "a2" => Ok(PaperSize::A2),
+            "a3" => Ok(PaperSize::A3),
+            "a4" => Ok(PaperSize::A4),
+            "a5" => Ok(PaperSize::A5),
+            "letter" => Ok(PaperSize::Letter),
+            "tabloid" => Ok(PaperSize::Tabloid),
+            custom => {
+                let (width_mm, height_mm) =
+                    parse_pair(custom).ok_or_else(|| format!("unknown paper size {}", s))?;
+                Ok(PaperSize::Custom {
+                    width_mm,
+                    height_mm,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    #[default]
+    Portrait,
+    Landscape,
+}
+
+impl std::str::FromStr for Orientation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "portrait" => Ok(Orientation::Portrait),
+            "landscape" => Ok(Orientation::Landscape),
+            _ => Err(format!(
+                "unknown orientation {}, expected portrait or landscape",
+                s
+            )),
+        }
+    }
+}
+
+/// How big the rendered map should be.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum OutputSize {
+    /// A fixed number of tiles, each drawn at `tile_size` pixels.
+    Grid {
+        columns: u32,
+        rows: u32,
+        #[serde(default = "default_tile_size")]
+        tile_size: u32,
+    },
+    /// A canvas of a fixed size in pixels, covered with tiles of `tile_size`
+    /// pixels.
+    Canvas {
+        width: u32,
+        height: u32,
+        #[serde(default = "default_tile_size")]
+        tile_size: u32,
+    },
+    /// A sheet of paper printed at `dpi`, covered with tiles `tile_mm`
+    /// millimetres across.
+    Paper {
+        paper: PaperSize,
+        #[serde(default = "default_dpi")]
+        dpi: u32,
+        #[serde(default)]
+        orientation: Orientation,
+        #[serde(default = "default_tile_mm")]
+        tile_mm: f64,
+    },
+}
+
+impl Default for OutputSize {
+    /// A4 portrait at 600 DPI.
+    fn default() -> Self {
+        OutputSize::Paper {
+            paper: PaperSize::A4,
+            dpi: DEFAULT_DPI,
+            orientation: Orientation::Portrait,
+            tile_mm: DEFAULT_TILE_MM,
+        }
+    }
+}
+
+fn default_tile_size() -> u32 {
+    DEFAULT_TILE_SIZE
+}
+
+fn default_dpi() -> u32 {
+    DEFAULT_DPI
+}
+
+fn default_tile_mm() -> f64 {
+    DEFAULT_TILE_MM
+}
+
+/// Canvas and tile sizes derived from an `OutputSize`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+    pub tile_size: u32,
+    /// Number of tiles needed to cover the canvas, partially visible tiles at
+    /// the right and bottom edges are included.
+    pub columns: u32,
+    pub rows: u32,
+}
+
+/// Reasons an `OutputSize` has no layout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayoutError {
+    /// The map would have no tiles along one side.
+    Empty,
+    /// The canvas would be wider or taller than the largest size in pixels.
+    TooLarge,
+}
+
+impl std::fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::Empty => write!(f, "the map must be at least one tile in each direction"),
+            LayoutError::TooLarge => write!(f, "the canvas is too large"),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+impl OutputSize {
+    pub fn layout(&self) -> Result<Layout, LayoutError> {
+        let layout = match *self {
+            OutputSize::Grid {
+                columns,
+                rows,
+                tile_size,
+            } => {
+                let tile_size = tile_size.max(1);
+                Layout {
+                    canvas_width: columns
+                        .checked_mul(tile_size)
+                        .ok_or(LayoutError::TooLarge)?,
+                    canvas_height: rows.checked_mul(tile_size).ok_or(LayoutError::TooLarge)?,
+                    tile_size,
+                    columns,
+                    rows,
+                }
+            }
+            OutputSize::Canvas {
+                width,
+                height,
+                tile_size,
+            } => Layout::covering(width, height, tile_size),
+            OutputSize::Paper {
+                paper,
+                dpi,
+                orientation,
+                tile_mm,
+            } => {
+                let (short, long) = paper.size_mm();
+                let (width_mm, height_mm) = match orientation {
+                    Orientation::Portrait => (short, long),
+                    Orientation::Landscape => (long, short),
+                };
+                let to_pixels = |mm: f64| {
+                    let pixels = (mm / MM_PER_INCH * dpi as f64).round();
+                    match pixels.is_nan() || pixels > u32::MAX as f64 {
+                        true => Err(LayoutError::TooLarge),
+                        false => Ok(pixels as u32),
+                    }
+                };
+                Layout::covering(
+                    to_pixels(width_mm)?,
+                    to_pixels(height_mm)?,
+                    to_pixels(tile_mm)?,
+                )
+            }
+        };
+        if layout.columns == 0 || layout.rows == 0 {
+            return Err(LayoutError::Empty);
+        }
+        Ok(layout)
+    }
+}
+
+impl Layout {
+    /// Drops partly visible tiles along the axes which wrap, so that the
+    /// canvas repeats seamlessly.
+    pub fn wrapped(self, wrap: Wrap) -> Layout {
+        let mut layout = self;
+        if wrap.x() {
+            layout.columns = (layout.canvas_width / layout.tile_size).max(1);
+            layout.canvas_width = layout.columns * layout.tile_size;
+        }
+        if wrap.y() {
+            layout.rows = (layout.canvas_height / layout.tile_size).max(1);
+            layout.canvas_height = layout.rows * layout.tile_size;
+        }
+        layout
+    }
+
+    fn covering(canvas_width: u32, canvas_height: u32, tile_size: u32) -> Self {
+        let tile_size = tile_size.max(1);
+        Layout {
+            canvas_width,
+            canvas_height,
+            tile_size,
+            columns: canvas_width.div_ceil(tile_size),
+            rows: canvas_height.div_ceil(tile_size),
+        }
+    }
+}
+
+/// Parses a pair of numbers written as `AxB`.
+pub fn parse_pair<T: std::str::FromStr>(s: &str) -> Option<(T, T)> {
+    let (a, b) = s.split_once('x')?;
+    Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_grid_has_exactly_its_tiles() {
+        let layout = OutputSize::Grid {
+            columns: 10,
+            rows: 6,
+            tile_size: 64,
+        }
+        .layout()
+        .unwrap();
+        assert_eq!((layout.columns, layout.rows), (10, 6));
+        assert_eq!((layout.canvas_width, layout.canvas_height), (640, 384));
+    }
+
+    #[test]
+    fn a_canvas_is_covered_by_partial_tiles() {
+        let layout = OutputSize::Canvas {
+            width: 1000,
+            height: 500,
+            tile_size: 128,
+        }
+        .layout()
+        .unwrap();
+        assert_eq!((layout.columns, layout.rows), (8, 4));
+        assert_eq!((layout.canvas_width, layout.canvas_height), (1000, 500));
+    }
+
+    #[test]
+    fn an_empty_or_huge_map_has_no_layout() {
+        let grid = |columns, rows, tile_size| {
+            OutputSize::Grid {
+                columns,
+                rows,
+                tile_size,
+            }
+            .layout()
+        };
+        assert_eq!(grid(0, 0, 128), Err(LayoutError::Empty));
+        assert_eq!(grid(40, 0, 128), Err(LayoutError::Empty));
+        assert_eq!(grid(40_000_000, 30, 128), Err(LayoutError::TooLarge));
+        let canvas = OutputSize::Canvas {
+            width: 0,
+            height: 500,
+            tile_size: 128,
+        };
+        assert_eq!(canvas.layout(), Err(LayoutError::Empty));
+        let paper = |dpi, tile_mm| {
+            OutputSize::Paper {
+                paper: PaperSize::A4,
+                dpi,
+                orientation: Orientation::Portrait,
+                tile_mm,
+            }
+            .layout()
+        };
+        assert_eq!(paper(u32::MAX, 45.0), Err(LayoutError::TooLarge));
+        assert_eq!(paper(600, f64::INFINITY), Err(LayoutError::TooLarge));
+        assert_eq!(paper(600, f64::NAN), Err(LayoutError::TooLarge));
+    }
+
+    #[test]
+    fn the_default_is_a4_at_600_dpi() {
+        let layout = OutputSize::default().layout().unwrap();
+        assert_eq!(layout.tile_size, DEFAULT_TILE_SIZE);
+        assert_eq!((layout.canvas_width, layout.canvas_height), (4961, 7016));
+        assert_eq!((layout.columns, layout.rows), (39, 55));
+        let landscape = OutputSize::Paper {
+            paper: PaperSize::A4,
+            dpi: DEFAULT_DPI,
+            orientation: Orientation::Landscape,
+            tile_mm: DEFAULT_TILE_MM,
+        }
+        .layout()
+        .unwrap();
+        assert_eq!((landscape.columns, landscape.rows), (55, 39));
+    }
+
+    #[test]
+    fn wrapping_drops_partial_tiles() {
+        let layout = OutputSize::Canvas {
+            width: 1000,
+            height: 500,
+            tile_size: 128,
+        }
+        .layout()
+        .unwrap()
+        .wrapped(Wrap::X);
+        assert_eq!((layout.columns, layout.rows), (7, 4));
+        assert_eq!((layout.canvas_width, layout.canvas_height), (896, 500));
+    }
+
+    #[test]
+    fn parses_paper_sizes() {
+        assert_eq!("A3".parse(), Ok(PaperSize::A3));
+        assert_eq!(
+            "300x200".parse(),
+            Ok(PaperSize::Custom {
+                width_mm: 300.0,
+                height_mm: 200.0
+            })
+        );
+        assert_eq!(
+            PaperSize::Custom {
+                width_mm: 300.0,
+                height_mm: 200.0
+            }
+            .size_mm(),
+            (200.0, 300.0)
+        );
+        assert!("b5".parse::<PaperSize>().is_err());
+        assert_eq!(parse_pair::<u32>("40 x 30"), Some((40, 30)));
+        assert_eq!(parse_pair::<u32>("40"), None);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::tile::{Feature, TileSpec};
+
+/// Offsets to the neighbouring cell across each edge, ordered north, east,
+/// south, west to match `TileSpec::edge_features`.
+pub const EDGE_OFFSETS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlacedTile {
+    pub tile_spec: u8,
+    pub rotation: u8,
+}
+
+/// Which features may run off the edge of the map.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BorderPolicy {
+    /// Anything may run off the map.
+    #[default]
+    Open,
+    /// Only fields touch the edge of the map, so every city, road and river
+    /// is finished inside it.
+    Closed,
+    /// Only roads and the river may run off the map, cities are finished
+    /// inside it.
+    RoadsOnly,
+}
+
+impl BorderPolicy {
+    /// Returns true if `feature` may be on an edge facing out of the map.
+    pub fn allows(&self, feature: Feature) -> bool {
+        match self {
+            BorderPolicy::Open => true,
+            BorderPolicy::Closed => feature == Feature::None,
+            BorderPolicy::RoadsOnly => feature != Feature::City,
+        }
+    }
+}
+
+impl std::str::FromStr for BorderPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(BorderPolicy::Open),
+            "closed" => Ok(BorderPolicy::Closed),
+            "roads-only" => Ok(BorderPolicy::RoadsOnly),
+            _ => Err(format!(
+                "unknown border policy {}, expected open, closed or roads-only",
+                s
+            )),
+        }
+    }
+}
+
+/// Which edges of the map join up with the opposite edge, so that it repeats
+/// seamlessly when laid side by side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Wrap {
+    #[default]
+    None,
+    /// The left edge joins the right edge.
+    X,
+    /// The top edge joins the bottom edge.
+    Y,
+    Both,
+}
+
+impl Wrap {
+    pub fn x(&self) -> bool {
+        matches!(self, Wrap::X | Wrap::Both)
+    }
+
+    pub fn y(&self) -> bool {
+        matches!(self, Wrap::Y | Wrap::Both)
+    }
+}
+
+impl std::str::FromStr for Wrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Wrap::None),
+            "x" => Ok(Wrap::X),
+            "y" => Ok(Wrap::Y),
+            "both" => Ok(Wrap::Both),
+            _ => Err(format!("unknown wrap {}, expected none, x, y or both", s)),
+        }
+    }
+}
+
+pub struct Map {
+    size_x: u32,
+    size_y: u32,
+    border: BorderPolicy,
+    wrap: Wrap,
+    pub(crate) specs: Vec<TileSpec>,
+    pub(crate) tiles: Vec<Vec<Option<PlacedTile>>>,
+    /// Cells holding a pinned tile, which stays put when the map is cleared.
+    pinned: Vec<Vec<bool>>,
+    /// Cells inside the shape of the map, those outside are treated as off
+    /// the map.
+    active: Vec<Vec<bool>>,
+    /// How urban each cell is, when the map's character varies from place
+    /// to place.
+    density: Option<Vec<Vec<i32>>>,
+}
+
+impl Map {
+    pub fn new(specs: Vec<TileSpec>, size_x: u32, size_y: u32) -> Self {
+        let mut tiles = Vec::new();
+        for _ in 0..size_y {
+            let mut row = Vec::new();
+            for _ in 0..size_x {
+                row.push(None);
+            }
+            tiles.push(row);
+        }
+        Map {
+            size_x,
+            size_y,
+            border: BorderPolicy::Open,
+            wrap: Wrap::None,
+            specs,
+            pinned: vec![vec![false; size_x as usize]; size_y as usize],
+            active: vec![vec![true; size_x as usize]; size_y as usize],
+            density: None,
+            tiles,
+        }
+    }
+
+    pub fn with_border(mut self, border: BorderPolicy) -> Self {
+        self.border = border;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: Wrap) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Limits the map to the cells set in `mask`, given row by row.
+    pub fn with_mask(mut self, mask: Vec<Vec<bool>>) -> Self {
+        self.active = mask;
+        self
+    }
+
+    /// Varies which tiles are favoured from cell to cell by `density`, given
+    /// row by row as returned by `Density::bias`.
+    pub fn with_density(mut self, density: Option<Vec<Vec<i32>>>) -> Self {
+        self.density = density;
+        self
+    }
+
+    pub fn border(&self) -> BorderPolicy {
+        self.border
+    }
+
+    pub fn wrap(&self) -> Wrap {
+        self.wrap
+    }
+
+    pub fn size_x(&self) -> u32 {
+        self.size_x
+    }
+
+    pub fn size_y(&self) -> u32 {
+        self.size_y
+    }
+
+    pub fn specs(&self) -> &[TileSpec] {
+        &self.specs
+    }
+
+    pub fn tile(&self, x: usize, y: usize) -> Option<&PlacedTile> {
+        self.tiles[y][x].as_ref()
+    }
+
+    /// Returns how urban the cell is, `None` if the map is the same
+    /// everywhere.
+    pub fn density(&self, x: usize, y: usize) -> Option<i32> {
+        self.density.as_ref().map(|density| density[y][x])
+    }
+
+    /// Returns true if the cell is inside the shape of the map.
+    pub fn is_active(&self, x: usize, y: usize) -> bool {
+        self.active[y][x]
+    }
+
+    /// Returns the cell at the given position, wrapping round to the other
+    /// side of the map along the axes which wrap, or `None` if it is off the
+    /// map or outside its shape.
+    pub fn cell(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let x = match self.wrap.x() && self.size_x > 0 {
+            true => x.rem_euclid(self.size_x as i32),
+            false => x,
+        };
+        let y = match self.wrap.y() && self.size_y > 0 {
+            true => y.rem_euclid(self.size_y as i32),
+            false => y,
+        };
+        let on_map = x >= 0 && y >= 0 && (x as u32) < self.size_x && (y as u32) < self.size_y;
+        (on_map && self.active[y as usize][x as usize]).then_some((x as usize, y as usize))
+    }
+
+    /// Returns the cell across the given edge (north, east, south, west) of
+    /// the cell at (`x`, `y`).
+    pub fn neighbour(&self, x: usize, y: usize, edge: usize) -> Option<(usize, usize)> {
+        let (dx, dy) = EDGE_OFFSETS[edge];
+        self.cell(x as i32 + dx, y as i32 + dy)
+    }
+
+    pub fn is_valid_position(&self, x: i32, y: i32) -> bool {
+        self.cell(x, y).is_some()
+    }
+
+    pub fn has_no_tile(&self, x: i32, y: i32) -> bool {
+        match self.cell(x, y) {
+            Some((x, y)) => self.tiles[y][x].is_none(),
+            None => false,
+        }
+    }
+
+    pub fn can_be_placed(&self, tile: u8, x: usize, y: usize, rotation: u8) -> bool {
+        let tile_spec = &self.specs[tile as usize];
+        for i in 0..4 {
+            let edge_feature: Feature = tile_spec.edge_features[(i + rotation as usize) % 4];
+
+            let Some((new_x, new_y)) = self.neighbour(x, y, i) else {
+                if !self.border.allows(edge_feature) {
+                    // Nothing but what the border allows may run off the map
+                    return false;
+                }
+                continue;
+            };
+            let other_tile = match (new_x, new_y) == (x, y) {
+                // A map one tile across which wraps meets itself
+                true => Some(PlacedTile {
+                    tile_spec: tile,
+                    rotation,
+                }),
+                false => self.tiles[new_y][new_x],
+            };
+            if let Some(other_tile) = other_tile {
+                let other_tile_spec = &self.specs[other_tile.tile_spec as usize];
+                let other_edge_feature =
+                    other_tile_spec.edge_features[(2 + i + other_tile.rotation as usize) % 4];
+                if other_edge_feature != edge_feature {
+                    // If the edge features do not match, we cannot place the tile
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Places a tile which is kept when the rest of the map is cleared.
+    pub fn pin(&mut self, x: usize, y: usize, tile: PlacedTile) {
+        self.tiles[y][x] = Some(tile);
+        self.pinned[y][x] = true;
+    }
+
+    pub fn is_pinned(&self, x: usize, y: usize) -> bool {
+        self.pinned[y][x]
+    }
+
+    /// Removes every tile which is not pinned.
+    pub fn clear_tiles(&mut self) {
+        for (row, pinned) in self.tiles.iter_mut().zip(&self.pinned) {
+            for (tile, &pinned) in row.iter_mut().zip(pinned) {
+                if !pinned {
+                    *tile = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::tile;
+    use crate::tile::Feature::{City, None as Field, Road};
+
+    fn specs() -> Vec<TileSpec> {
+        vec![
+            tile("roadns", [Road, Field, Road, Field]),
+            tile("cityn", [City, Field, Field, Field]),
+        ]
+    }
+
+    #[test]
+    fn tiles_must_match_their_neighbours() {
+        let mut map = Map::new(specs(), 2, 1);
+        // Turned once the road runs east to west
+        map.tiles[0][0] = Some(PlacedTile {
+            tile_spec: 0,
+            rotation: 1,
+        });
+        assert!(map.can_be_placed(0, 1, 0, 1));
+        assert!(map.can_be_placed(0, 1, 0, 3));
+        assert!(!map.can_be_placed(0, 1, 0, 0));
+        assert!(!map.can_be_placed(1, 1, 0, 0));
+    }
+
+    #[test]
+    fn the_border_limits_what_runs_off_the_map() {
+        let open = Map::new(specs(), 1, 1);
+        assert!(open.can_be_placed(1, 0, 0, 0));
+        let closed = Map::new(specs(), 1, 1).with_border(BorderPolicy::Closed);
+        assert!(!closed.can_be_placed(0, 0, 0, 0));
+        assert!(!closed.can_be_placed(1, 0, 0, 0));
+        let roads = Map::new(specs(), 1, 1).with_border(BorderPolicy::RoadsOnly);
+        assert!(roads.can_be_placed(0, 0, 0, 0));
+        assert!(!roads.can_be_placed(1, 0, 0, 2));
+    }
+
+    #[test]
+    fn wrapping_joins_opposite_edges() {
+        let map = Map::new(specs(), 3, 2).with_wrap(Wrap::X);
+        assert_eq!(map.cell(-1, 0), Some((2, 0)));
+        assert_eq!(map.cell(3, 1), Some((0, 1)));
+        assert_eq!(map.cell(0, -1), None);
+        assert_eq!(map.neighbour(0, 0, 3), Some((2, 0)));
+        assert_eq!(map.neighbour(0, 1, 2), None);
+    }
+
+    #[test]
+    fn cells_outside_the_mask_are_off_the_map() {
+        let map = Map::new(specs(), 2, 1).with_mask(vec![vec![true, false]]);
+        assert!(map.is_active(0, 0));
+        assert!(!map.is_active(1, 0));
+        assert_eq!(map.neighbour(0, 0, 1), None);
+        assert!(!map.is_valid_position(1, 0));
+        assert!(!map.has_no_tile(1, 0));
+        assert!(map.has_no_tile(0, 0));
+    }
+
+    #[test]
+    fn clearing_keeps_pinned_tiles() {
+        let mut map = Map::new(specs(), 2, 1);
+        let tile = PlacedTile {
+            tile_spec: 1,
+            rotation: 0,
+        };
+        map.pin(0, 0, tile);
+        map.tiles[0][1] = Some(tile);
+        map.clear_tiles();
+        assert_eq!(map.tile(0, 0), Some(&tile));
+        assert!(map.is_pinned(0, 0));
+        assert_eq!(map.tile(1, 0), None);
+    }
+
+    #[test]
+    fn parses_border_policies_and_wraps() {
+        assert_eq!("roads-only".parse(), Ok(BorderPolicy::RoadsOnly));
+        assert!("shut".parse::<BorderPolicy>().is_err());
+        assert_eq!("both".parse(), Ok(Wrap::Both));
+        assert!(Wrap::Y.y() && !Wrap::Y.x());
+    }
+}
@@ -0,0 +1,23 @@
+//! Shapes and colours for the placeholder drawn when a tile's art is missing.
+//!
+//! Sizes are fractions of half the tile size, each edge feature is drawn as
+//! if it were on the north edge and then turned into place.
+
+pub const FIELD_COLOUR: [u8; 3] = [0x7c, 0xb3, 0x42];
+pub const CITY_COLOUR: [u8; 3] = [0xc8, 0x9b, 0x5a];
+pub const ROAD_COLOUR: [u8; 3] = [0xee, 0xee, 0xe4];
+pub const RIVER_COLOUR: [u8; 3] = [0x4f, 0x8f, 0xd8];
+pub const CLOISTER_COLOUR: [u8; 3] = [0xb0, 0x3a, 0x2e];
+
+/// How far a city reaches in from its edge.
+pub const CITY_DEPTH: f64 = 0.5;
+/// Half widths of roads and rivers.
+pub const ROAD_WIDTH: f64 = 0.12;
+pub const RIVER_WIDTH: f64 = 0.22;
+/// Half size of the cloister in the middle of the tile.
+pub const CLOISTER_SIZE: f64 = 0.3;
+
+/// Formats a colour for use as a CSS fill style.
+pub fn css_colour(colour: [u8; 3]) -> String {
+    format!("rgb({}, {}, {})", colour[0], colour[1], colour[2])
+}
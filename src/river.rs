@@ -0,0 +1,900 @@
+use crate::deck::Deck;
+use crate::error::GenerateError;
+use crate::generator::{build_draw_deck, empty_cells, RiverConfig, RiverMouth};
+use crate::map::{Map, PlacedTile};
+use crate::rng::Rng;
+use crate::solver::{self, Candidate};
+use crate::tile::{Expansion, Feature, RiverEnd, TileSpec};
+
+/// Number of tiles which may be tried along a course before giving up on
+/// tiling it.
+const MAX_TILE_TRIES: usize = 10_000;
+
+/// Number of times the rivers are planned afresh before giving up.
+const MAX_PLANS: usize = 1000;
+
+/// Percentage chance a river forks at each tile while there are forks left to
+/// place.
+const FORK_CHANCE: usize = 20;
+
+/// A cell on a river's course and the edges the river crosses, one bit per
+/// edge north, east, south, west. A cell with three edges is a fork, or where
+/// another river flows in.
+#[derive(Clone, Copy, Debug)]
+struct Step {
+    x: usize,
+    y: usize,
+    edges: u8,
+    end: Option<RiverEnd>,
+}
+
+/// The river tiles which may be placed on each kind of step.
+struct Pools {
+    sources: Vec<Candidate>,
+    lakes: Vec<Candidate>,
+    forks: Vec<Candidate>,
+    middle: Vec<Candidate>,
+}
+
+impl Pools {
+    fn for_step(&self, step: &Step) -> &[Candidate] {
+        match step.end {
+            Some(RiverEnd::Source) => &self.sources,
+            Some(RiverEnd::Lake) => &self.lakes,
+            None if step.edges.count_ones() == 3 => &self.forks,
+            None => &self.middle,
+        }
+    }
+}
+
+/// Lays the rivers, drawing tiles from `deck`, planning their courses afresh
+/// until they can be tiled and leave no cell which none of `fillers` fits.
+/// Only the river is planned again, the tiles already on the map are kept.
+pub fn place_river_tiles(
+    map: &mut Map,
+    deck: &mut Deck,
+    river: &RiverConfig,
+    fillers: &[Candidate],
+    rng: &mut Rng,
+) -> Result<(), GenerateError> {
+    for _ in 0..MAX_PLANS {
+        let Some(steps) = lay_rivers(map, deck, river, rng) else {
+            continue;
+        };
+        // A river which leaves a cell no base tile fits is no use either
+        if solver::first_unfillable(map, fillers, &empty_cells(map)).is_none() {
+            return Ok(());
+        }
+        for step in &steps {
+            solver::clear(map, deck, step.x, step.y);
+        }
+    }
+    Err(GenerateError::RiverPlacementFailed)
+}
+
+/// Lays the rivers once, drawing tiles from `deck`. Returns the steps laid,
+/// or `None` with the map and deck left as they were if no course could be
+/// planned or it could not be tiled with the tiles in the deck.
+///
+/// The course of every river is planned first as a walk from the upstream
+/// edge of the map in the direction the rivers flow, turning aside as much as
+/// `river` says they meander. When the catalog has a source tile each river
+/// rises there, otherwise it flows in from off the map. Each arm of a river
+/// ends in a lake once it is long enough or flows off the map, as `river`
+/// says, unless it flows into another river. An arm left without a lake in
+/// the deck flows off the map instead. The courses are then tiled with
+/// river tiles whose river edges follow them.
+fn lay_rivers(
+    map: &mut Map,
+    deck: &mut Deck,
+    river: &RiverConfig,
+    rng: &mut Rng,
+) -> Option<Vec<Step>> {
+    let draw_deck = build_draw_deck(map, Expansion::River);
+    let with = |keep: fn(&TileSpec) -> bool| -> Vec<Candidate> {
+        let tiles: Vec<u8> = draw_deck
+            .iter()
+            .copied()
+            .filter(|&tile| keep(&map.specs()[tile as usize]))
+            .collect();
+        solver::candidates(map, &tiles)
+    };
+    let pools = Pools {
+        sources: with(|spec| spec.river_end == Some(RiverEnd::Source)),
+        lakes: with(|spec| spec.river_end == Some(RiverEnd::Lake)),
+        forks: with(|spec| spec.river_end.is_none() && is_fork(spec)),
+        middle: with(|spec| spec.river_end.is_none() && !is_fork(spec)),
+    };
+    let flow = river.direction.edge().unwrap_or_else(|| rng.below(4));
+    let wraps_downstream = match flow % 2 {
+        0 => map.wrap().y(),
+        _ => map.wrap().x(),
+    };
+    // A lake is the only way to end a river when it cannot leave the map
+    let ends_in_lake = !pools.lakes.is_empty()
+        && (river.mouth == RiverMouth::Lake
+            || wraps_downstream
+            || !map.border().allows(Feature::River));
+
+    let mut planner = Planner::new(
+        map,
+        river,
+        flow,
+        !pools.sources.is_empty(),
+        ends_in_lake,
+        !pools.forks.is_empty(),
+        deck,
+    )?;
+    let pinned = planner.plan_from_pins(rng)?;
+    for r in pinned..pinned.max(river.count as usize) {
+        if !planner.plan_river(r, rng) {
+            return None;
+        }
+    }
+    let steps = planner.steps;
+    lay_course(map, deck, &steps, &pools, rng).then_some(steps)
+}
+
+/// The end of one arm of a river which is still being planned.
+struct Branch {
+    step: usize,
+    /// Edge the river flowed across into the step.
+    heading: usize,
+    /// Number of tiles from the start of the river.
+    length: usize,
+    /// Edge of the map the arm flows towards, upstream or downstream.
+    flow: usize,
+    /// Tile the arm ends on once long enough, or `None` if it ends by running
+    /// off the map.
+    end: Option<RiverEnd>,
+    /// Whether the arm is held to the length of the river, false for an arm
+    /// forked off without a lake of its own which runs on to the edge of the
+    /// map.
+    bounded: bool,
+}
+
+/// Where a river flows on to from a cell.
+enum Next {
+    /// An empty cell.
+    Cell(usize, usize),
+    /// The given step of another river, which it flows into.
+    Join(usize),
+    /// Off the edge of the map.
+    Off,
+}
+
+/// Plans the courses of the rivers one after another, keeping each clear of
+/// those already planned.
+struct Planner<'a> {
+    map: &'a Map,
+    river: &'a RiverConfig,
+    flow: usize,
+    from_source: bool,
+    ends_in_lake: bool,
+    /// Whether there are fork tiles to split a river or let one flow into
+    /// another.
+    has_forks: bool,
+    min: usize,
+    max: usize,
+    /// Number of forks still to place.
+    forks: usize,
+    /// Number of lakes left in the deck for arms not yet ending in one.
+    lakes: usize,
+    steps: Vec<Step>,
+    /// The river and step on each cell.
+    cells: Vec<Option<(usize, usize)>>,
+}
+
+impl<'a> Planner<'a> {
+    /// Returns `None` if the river can never be as long as it is asked to be.
+    fn new(
+        map: &'a Map,
+        river: &'a RiverConfig,
+        flow: usize,
+        from_source: bool,
+        ends_in_lake: bool,
+        has_forks: bool,
+        deck: &Deck,
+    ) -> Option<Self> {
+        let in_deck = |keep: &dyn Fn(&TileSpec) -> bool| {
+            (0..map.specs().len())
+                .filter(|&tile| keep(&map.specs()[tile]))
+                .map(|tile| deck.count(tile as u8) as usize)
+                .fold(0, usize::saturating_add)
+        };
+        // River tiles left for each river, a finite deck sharing its own
+        let available =
+            in_deck(&|spec| spec.expansion == Expansion::River) / river.count.max(1) as usize;
+        let shortest = (from_source as usize + ends_in_lake as usize).max(1);
+        let max = river.max_length.map(|n| n as usize);
+        let span = match flow % 2 {
+            0 => map.size_y(),
+            _ => map.size_x(),
+        } as usize;
+        let default_min = match ends_in_lake {
+            // One box's worth of river tiles, or as many as the map is long
+            // in the direction of flow when that is more, so the river
+            // reaches well into a large map
+            true => build_draw_deck(map, Expansion::River)
+                .len()
+                .max(span)
+                .min(available),
+            false => 1,
+        };
+        let min = river
+            .min_length
+            .map_or(default_min.min(max.unwrap_or(usize::MAX)), |n| n as usize)
+            .max(shortest);
+        let max = max.unwrap_or(match ends_in_lake {
+            true => min,
+            false => usize::MAX,
+        });
+        let forks = match river.forks {
+            Some(forks) => forks as usize,
+            // As many forks as come in one box
+            None => build_draw_deck(map, Expansion::River)
+                .iter()
+                .filter(|&&tile| is_fork(&map.specs()[tile as usize]))
+                .count(),
+        }
+        .min(in_deck(&|spec| {
+            spec.expansion == Expansion::River && spec.river_end.is_none() && is_fork(spec)
+        }));
+        let lakes = in_deck(&|spec| spec.river_end == Some(RiverEnd::Lake));
+        let cells = map.size_x() as usize * map.size_y() as usize;
+        (min <= max && cells > 0).then(|| Planner {
+            map,
+            river,
+            flow,
+            from_source,
+            ends_in_lake,
+            has_forks,
+            min,
+            max,
+            forks: match has_forks {
+                true => forks,
+                false => 0,
+            },
+            lakes,
+            steps: Vec::new(),
+            cells: vec![None; cells],
+        })
+    }
+
+    /// Plans river `r` from a free cell on the upstream edge of the map's
+    /// shape, returning false if it could not be finished.
+    fn plan_river(&mut self, r: usize, rng: &mut Rng) -> bool {
+        let (size_x, size_y) = (self.map.size_x() as usize, self.map.size_y() as usize);
+        let target = self.min + rng.below((self.max - self.min).min(size_x * size_y) + 1);
+
+        let (across, along) = match self.flow % 2 {
+            0 => (size_x, size_y),
+            _ => (size_y, size_x),
+        };
+        // The first cell of the map's shape in a random lane, coming in from
+        // the upstream edge
+        let start = (0..across)
+            .filter_map(|_| {
+                let lane = rng.below(across);
+                (0..along)
+                    .map(|i| match self.flow {
+                        0 => (lane, size_y - 1 - i),
+                        1 => (i, lane),
+                        2 => (lane, i),
+                        _ => (size_x - 1 - i, lane),
+                    })
+                    .find(|&(x, y)| self.map.is_active(x, y))
+            })
+            .find(|&cell| self.is_free(r, cell, None));
+        let (Some((x, y)), Some(end)) = (start, self.arm_end()) else {
+            return false;
+        };
+        let step = match self.from_source {
+            true => self.add_step(r, x, y, 0, Some(RiverEnd::Source)),
+            // The river flows in from off the upstream edge
+            false => self.add_step(r, x, y, 1 << ((self.flow + 2) % 4), None),
+        };
+        let branch = Branch {
+            step,
+            heading: self.flow,
+            length: 1,
+            flow: self.flow,
+            end,
+            bounded: true,
+        };
+        self.plan_branches(r, vec![branch], target, rng)
+    }
+
+    /// Plans a river on from each pinned river tile with its river edges
+    /// facing empty cells, returning how many rivers were planned or `None`
+    /// if one could not be finished.
+    ///
+    /// A river flows downstream from a pinned source and upstream from a
+    /// pinned lake. From any other pinned tile it flows downstream across its
+    /// first open edge and upstream across the others.
+    fn plan_from_pins(&mut self, rng: &mut Rng) -> Option<usize> {
+        let (size_x, size_y) = (self.map.size_x() as usize, self.map.size_y() as usize);
+        let mut rivers = 0;
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let Some(tile) = self.map.tile(x, y).filter(|_| self.map.is_pinned(x, y)) else {
+                    continue;
+                };
+                let spec = &self.map.specs()[tile.tile_spec as usize];
+                let openings: Vec<(usize, (usize, usize))> = (0..4)
+                    .filter(|&edge| spec.edge(edge, tile.rotation) == Feature::River)
+                    .filter_map(|edge| Some((edge, self.map.neighbour(x, y, edge)?)))
+                    .filter(|&(_, (nx, ny))| self.map.tile(nx, ny).is_none())
+                    .collect();
+                if openings.is_empty() {
+                    continue;
+                }
+
+                let r = rivers;
+                rivers += 1;
+                let target = self.min + rng.below((self.max - self.min).min(size_x * size_y) + 1);
+                let mut branches = Vec::new();
+                for (i, (edge, cell)) in openings.into_iter().enumerate() {
+                    let downstream = match spec.river_end {
+                        Some(RiverEnd::Source) => true,
+                        Some(RiverEnd::Lake) => false,
+                        None => i == 0,
+                    };
+                    let (flow, end) = match downstream {
+                        true => (self.flow, self.arm_end()?),
+                        false => (
+                            (self.flow + 2) % 4,
+                            self.from_source.then_some(RiverEnd::Source),
+                        ),
+                    };
+                    if !self.is_free(r, cell, Some((x, y))) {
+                        return None;
+                    }
+                    branches.push(Branch {
+                        step: self.add_step(r, cell.0, cell.1, 1 << ((edge + 2) % 4), None),
+                        heading: edge,
+                        length: 2,
+                        flow,
+                        end,
+                        bounded: true,
+                    });
+                }
+                if !self.plan_branches(r, branches, target, rng) {
+                    return None;
+                }
+            }
+        }
+        Some(rivers)
+    }
+
+    /// Carries every arm of river `r` on until it ends, returning false if
+    /// one gets stuck.
+    fn plan_branches(
+        &mut self,
+        r: usize,
+        mut branches: Vec<Branch>,
+        target: usize,
+        rng: &mut Rng,
+    ) -> bool {
+        while let Some(branch) = branches.pop() {
+            let Some(arms) = self.flow_on(r, branch, target, rng) else {
+                return false;
+            };
+            branches.extend(arms);
+        }
+        true
+    }
+
+    /// Carries a river on from the end of `branch`, returning the arms it
+    /// continues in, none once it has ended, or `None` if it is stuck.
+    fn flow_on(
+        &mut self,
+        r: usize,
+        branch: Branch,
+        target: usize,
+        rng: &mut Rng,
+    ) -> Option<Vec<Branch>> {
+        if let Some(end) = branch.end.filter(|_| branch.length >= target) {
+            self.steps[branch.step].end = Some(end);
+            return Some(Vec::new());
+        }
+
+        let Step { x, y, end, .. } = self.steps[branch.step];
+        let exits: Vec<(usize, Next)> = self
+            .headings(branch.heading, branch.flow, rng)
+            .into_iter()
+            .filter_map(|h| self.next(r, x, y, h, &branch).map(|next| (h, next)))
+            .collect();
+        let mut exits = exits.into_iter();
+        let first = exits.next()?;
+        // Only split downstream, each new arm ending in a lake of its own or
+        // off the map
+        let forks = self.forks > 0
+            && end.is_none()
+            && branch.flow == self.flow
+            && branch.length > 1
+            && rng.below(100) < FORK_CHANCE;
+        let second = match forks {
+            true => exits.find(|(_, next)| !matches!(next, Next::Join(_))),
+            false => None,
+        };
+        let second = second.and_then(|exit| Some((exit, self.arm_end()?)));
+        if second.is_some() {
+            self.forks -= 1;
+        }
+
+        let mut arms = Vec::new();
+        let first = (first, branch.end, branch.bounded);
+        let second = second.map(|(exit, end)| (exit, end, end.is_some()));
+        for ((h, next), end, bounded) in [Some(first), second].into_iter().flatten() {
+            self.steps[branch.step].edges |= 1 << h;
+            let back = 1 << ((h + 2) % 4);
+            match next {
+                Next::Cell(x, y) => arms.push(Branch {
+                    step: self.add_step(r, x, y, back, None),
+                    heading: h,
+                    length: branch.length + 1,
+                    flow: branch.flow,
+                    end,
+                    bounded,
+                }),
+                Next::Join(step) => {
+                    self.steps[step].edges |= back;
+                    // Ending in another river, the arm's lake is left over
+                    if end == Some(RiverEnd::Lake) {
+                        self.lakes += 1;
+                    }
+                }
+                Next::Off => {}
+            }
+        }
+        Some(arms)
+    }
+
+    /// Returns the edges an arm flowing towards the `flow` edge and heading
+    /// across `heading` may flow on across, most wanted first. It never turns
+    /// back away from `flow`.
+    fn headings(&self, heading: usize, flow: usize, rng: &mut Rng) -> Vec<usize> {
+        let sides = [(flow + 1) % 4, (flow + 3) % 4];
+        let turns = rng.below(100) < self.river.meander as usize;
+        let preferred = match (heading == flow, turns) {
+            (true, true) => sides[rng.below(2)],
+            (false, true) if sides.contains(&heading) => heading,
+            _ => flow,
+        };
+        let mut others: Vec<usize> = [flow, sides[0], sides[1]]
+            .into_iter()
+            .filter(|&h| h != preferred)
+            .collect();
+        if rng.below(2) == 1 {
+            others.reverse();
+        }
+        let mut headings = vec![preferred];
+        headings.extend(others);
+        headings
+    }
+
+    /// Returns where `branch` of river `r`, ending at (`x`, `y`), could flow
+    /// across edge `h`, or `None` if it may not.
+    fn next(&self, r: usize, x: usize, y: usize, h: usize, branch: &Branch) -> Option<Next> {
+        let length = branch.length;
+        let Some(cell) = self.map.neighbour(x, y, h) else {
+            // Only leave in the direction of flow, so the river crosses the map
+            let leaves = branch.end.is_none()
+                && h == branch.flow
+                && (length >= self.min || !branch.bounded)
+                && self.map.border().allows(Feature::River);
+            return leaves.then_some(Next::Off);
+        };
+        if let Some((other, step)) = self.cells[self.index(cell)] {
+            let target = &self.steps[step];
+            let joins = self.river.merge
+                && self.has_forks
+                && other != r
+                && target.end.is_none()
+                && target.edges.count_ones() == 2;
+            return joins.then_some(Next::Join(step));
+        }
+        ((length < self.max || !branch.bounded) && self.is_free(r, cell, Some((x, y))))
+            .then_some(Next::Cell(cell.0, cell.1))
+    }
+
+    /// Returns true if river `r` may flow into `cell` from the cell `from`,
+    /// it being empty and not beside any other part of a river it should keep
+    /// apart from.
+    fn is_free(&self, r: usize, cell: (usize, usize), from: Option<(usize, usize)>) -> bool {
+        if self.cells[self.index(cell)].is_some()
+            || self.map.tile(cell.0, cell.1).is_some()
+            || !self.map.is_active(cell.0, cell.1)
+        {
+            return false;
+        }
+        self.river.self_adjacent
+            || (0..4).all(|edge| match self.map.neighbour(cell.0, cell.1, edge) {
+                Some(other) if Some(other) != from => match self.cells[self.index(other)] {
+                    // Rivers which may join can run side by side
+                    Some((owner, _)) => self.river.merge && owner != r,
+                    None => true,
+                },
+                _ => true,
+            })
+    }
+
+    fn add_step(
+        &mut self,
+        r: usize,
+        x: usize,
+        y: usize,
+        edges: u8,
+        end: Option<RiverEnd>,
+    ) -> usize {
+        let index = self.index((x, y));
+        self.cells[index] = Some((r, self.steps.len()));
+        self.steps.push(Step { x, y, edges, end });
+        self.steps.len() - 1
+    }
+
+    /// Returns the tile a new arm flowing downstream ends on, taking a lake
+    /// for it when the rivers end in lakes and there are any left, otherwise
+    /// `None` as it flows off the map. Returns `None` if the arm can end
+    /// neither way.
+    fn arm_end(&mut self) -> Option<Option<RiverEnd>> {
+        if self.ends_in_lake && self.lakes > 0 {
+            self.lakes -= 1;
+            return Some(Some(RiverEnd::Lake));
+        }
+        let wraps_downstream = match self.flow % 2 {
+            0 => self.map.wrap().y(),
+            _ => self.map.wrap().x(),
+        };
+        (!wraps_downstream && self.map.border().allows(Feature::River)).then_some(None)
+    }
+
+    fn index(&self, (x, y): (usize, usize)) -> usize {
+        y * self.map.size_x() as usize + x
+    }
+}
+
+/// Returns true if the river splits in two on the tile.
+fn is_fork(spec: &TileSpec) -> bool {
+    spec.edge_features
+        .iter()
+        .filter(|&&feature| feature == Feature::River)
+        .count()
+        == 3
+}
+
+/// Places a tile on every step of the course, backtracking to earlier steps
+/// when nothing left in the deck fits. Returns false, with the course left
+/// empty, if it cannot be tiled.
+fn lay_course(
+    map: &mut Map,
+    deck: &mut Deck,
+    steps: &[Step],
+    pools: &Pools,
+    rng: &mut Rng,
+) -> bool {
+    // Tiles still to try on each step placed so far and the one being placed
+    let mut options: Vec<Vec<Candidate>> = Vec::new();
+    let mut tries = 0;
+    let mut i = 0;
+    while i < steps.len() {
+        let step = &steps[i];
+        if options.len() == i {
+            options.push(
+                pools
+                    .for_step(step)
+                    .iter()
+                    .filter(|c| {
+                        deck.has(c.tile)
+                            && river_edges(map, c.tile, c.rotation) == step.edges
+                            && map.can_be_placed(c.tile, step.x, step.y, c.rotation)
+                    })
+                    .copied()
+                    .collect(),
+            );
+        }
+        if options[i].is_empty() {
+            options.pop();
+            if i == 0 || tries >= MAX_TILE_TRIES {
+                for step in &steps[..i] {
+                    solver::clear(map, deck, step.x, step.y);
+                }
+                return false;
+            }
+            i -= 1;
+            solver::clear(map, deck, steps[i].x, steps[i].y);
+            continue;
+        }
+
+        tries += 1;
+        let candidate = solver::take_weighted(&mut options[i], rng);
+        deck.take(candidate.tile);
+        map.tiles[step.y][step.x] = Some(PlacedTile {
+            tile_spec: candidate.tile,
+            rotation: candidate.rotation,
+        });
+        i += 1;
+    }
+    true
+}
+
+/// Returns the edges of a tile with the river on them, one bit per edge.
+fn river_edges(map: &Map, tile: u8, rotation: u8) -> u8 {
+    let spec = &map.specs()[tile as usize];
+    (0..4)
+        .filter(|&edge| spec.edge(edge, rotation) == Feature::River)
+        .fold(0, |edges, edge| edges | 1 << edge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::{edges_match, highres, tile};
+    use crate::generator::FlowDirection;
+    use crate::tile::Feature::{None as Field, River};
+
+    /// The shipped catalog with a fork tile, the river coming in from the
+    /// west and splitting to the east and south.
+    fn with_fork() -> Vec<TileSpec> {
+        let mut specs = highres();
+        let mut fork = tile("riverfork", [Field, River, River, River]);
+        fork.expansion = Expansion::River;
+        specs.push(fork);
+        specs
+    }
+
+    /// Lays the rivers on a 16 by 12 map with the fork tile, drawing from the
+    /// deck `deck` makes, trying seeds until one gives a map `wanted` accepts.
+    fn laid_with_fork(
+        river: &RiverConfig,
+        deck: impl Fn(&Map) -> Deck,
+        wanted: impl Fn(&Map) -> bool,
+    ) -> Map {
+        (0..200)
+            .find_map(|seed| {
+                let mut map = Map::new(with_fork(), 16, 12);
+                let mut deck = deck(&map);
+                let placed = lay_rivers(&mut map, &mut deck, river, &mut Rng::new(seed)).is_some();
+                (placed && wanted(&map)).then_some(map)
+            })
+            .expect("no seed laid the rivers as wanted")
+    }
+
+    fn forks(map: &Map) -> usize {
+        river_tiles(map)
+            .into_iter()
+            .filter(|(_, _, spec, _)| is_fork(spec))
+            .count()
+    }
+
+    /// Returns true if a river edge runs off the map.
+    fn leaves_the_map(map: &Map) -> bool {
+        river_tiles(map).iter().any(|&(x, y, spec, rotation)| {
+            (0..4).any(|edge| {
+                spec.edge(edge, rotation) == Feature::River && map.neighbour(x, y, edge).is_none()
+            })
+        })
+    }
+
+    /// Returns the river tiles on the map with their positions.
+    fn river_tiles(map: &Map) -> Vec<(usize, usize, &TileSpec, u8)> {
+        let mut tiles = Vec::new();
+        for y in 0..map.size_y() as usize {
+            for x in 0..map.size_x() as usize {
+                if let Some(tile) = map.tile(x, y) {
+                    let spec = &map.specs()[tile.tile_spec as usize];
+                    if spec.expansion == Expansion::River {
+                        tiles.push((x, y, spec, tile.rotation));
+                    }
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Returns true if every river edge runs on into another tile or off
+    /// the map.
+    fn flows_on(map: &Map) -> bool {
+        river_tiles(map).iter().all(|&(x, y, spec, rotation)| {
+            (0..4)
+                .filter(|&edge| spec.edge(edge, rotation) == Feature::River)
+                .all(|edge| {
+                    map.neighbour(x, y, edge)
+                        .is_none_or(|(nx, ny)| map.tile(nx, ny).is_some())
+                })
+        })
+    }
+
+    fn ends(map: &Map, end: RiverEnd) -> Vec<(usize, usize)> {
+        river_tiles(map)
+            .into_iter()
+            .filter(|(_, _, spec, _)| spec.river_end == Some(end))
+            .map(|(x, y, _, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn lays_one_box_from_source_to_lake() {
+        let mut map = Map::new(highres(), 12, 9);
+        let river = RiverConfig::default();
+        assert!(lay_rivers(&mut map, &mut Deck::unlimited(), &river, &mut Rng::new(1)).is_some());
+        assert_eq!(river_tiles(&map).len(), 10);
+        assert_eq!(ends(&map, RiverEnd::Source).len(), 1);
+        assert_eq!(ends(&map, RiverEnd::Lake).len(), 1);
+        assert!(edges_match(&map));
+        assert!(flows_on(&map));
+    }
+
+    #[test]
+    fn a_lake_river_reaches_across_a_large_map() {
+        let river = RiverConfig {
+            direction: FlowDirection::South,
+            ..RiverConfig::default()
+        };
+        let mut map = Map::new(highres(), 39, 55);
+        let mut deck = Deck::unlimited();
+        assert!(lay_rivers(&mut map, &mut deck, &river, &mut Rng::new(2)).is_some());
+        assert_eq!(river_tiles(&map).len(), 55);
+        assert_eq!(ends(&map, RiverEnd::Lake).len(), 1);
+        assert!(flows_on(&map));
+
+        // Unless the deck has too few river tiles, when a box's worth is laid
+        // however many tries it takes to fit them
+        let laid = (0..100).find_map(|seed| {
+            let mut map = Map::new(highres(), 39, 55);
+            let mut deck = Deck::finite(map.specs(), 1);
+            lay_rivers(&mut map, &mut deck, &river, &mut Rng::new(seed))
+                .is_some()
+                .then_some(map)
+        });
+        assert_eq!(river_tiles(&laid.unwrap()).len(), 10);
+    }
+
+    #[test]
+    fn rises_on_the_upstream_edge() {
+        let mut map = Map::new(highres(), 10, 12);
+        let river = RiverConfig {
+            direction: FlowDirection::North,
+            ..RiverConfig::default()
+        };
+        assert!(lay_rivers(&mut map, &mut Deck::unlimited(), &river, &mut Rng::new(2)).is_some());
+        let source = ends(&map, RiverEnd::Source);
+        assert_eq!(source.len(), 1);
+        assert_eq!(source[0].1, 11);
+    }
+
+    #[test]
+    fn a_border_mouth_flows_off_the_map() {
+        let mut map = Map::new(highres(), 6, 4);
+        let river = RiverConfig {
+            mouth: RiverMouth::Border,
+            direction: FlowDirection::East,
+            ..RiverConfig::default()
+        };
+        assert!(lay_rivers(&mut map, &mut Deck::unlimited(), &river, &mut Rng::new(3)).is_some());
+        assert!(ends(&map, RiverEnd::Lake).is_empty());
+        assert!(flows_on(&map));
+        assert!(river_tiles(&map)
+            .iter()
+            .any(|&(x, _, spec, rotation)| x == 5 && spec.edge(1, rotation) == Feature::River));
+    }
+
+    #[test]
+    fn several_rivers_each_rise_at_a_source() {
+        let mut map = Map::new(highres(), 16, 12);
+        let river = RiverConfig {
+            count: 2,
+            mouth: RiverMouth::Border,
+            ..RiverConfig::default()
+        };
+        let mut deck = Deck::unlimited();
+        assert!(lay_rivers(&mut map, &mut deck, &river, &mut Rng::new(4)).is_some());
+        assert_eq!(ends(&map, RiverEnd::Source).len(), 2);
+        assert!(flows_on(&map));
+    }
+
+    #[test]
+    fn a_fork_splits_the_river_into_two_arms() {
+        let river = RiverConfig {
+            direction: FlowDirection::South,
+            forks: Some(1),
+            ..RiverConfig::default()
+        };
+        let map = laid_with_fork(&river, |_| Deck::unlimited(), |map| forks(map) == 1);
+        assert_eq!(ends(&map, RiverEnd::Source).len(), 1);
+        assert_eq!(ends(&map, RiverEnd::Lake).len(), 2);
+        assert!(edges_match(&map));
+        assert!(flows_on(&map));
+    }
+
+    #[test]
+    fn an_arm_without_a_lake_flows_off_the_map() {
+        let river = RiverConfig {
+            direction: FlowDirection::South,
+            forks: Some(1),
+            ..RiverConfig::default()
+        };
+        // Two boxes of river tiles but only one lake, so the second arm
+        // leaves the map
+        let one_lake = |map: &Map| {
+            let mut deck = Deck::finite(map.specs(), 2);
+            let lake = map
+                .specs()
+                .iter()
+                .position(|spec| spec.river_end == Some(RiverEnd::Lake));
+            deck.take(lake.unwrap() as u8);
+            deck
+        };
+        let map = laid_with_fork(&river, one_lake, |map| forks(map) == 1);
+        assert_eq!(ends(&map, RiverEnd::Lake).len(), 1);
+        assert!(leaves_the_map(&map));
+        assert!(edges_match(&map));
+        assert!(flows_on(&map));
+
+        // Without a border to leave by the river never forks
+        let mut map = Map::new(with_fork(), 16, 12).with_border(crate::map::BorderPolicy::Closed);
+        let mut deck = Deck::finite(map.specs(), 1);
+        if lay_rivers(&mut map, &mut deck, &river, &mut Rng::new(1)).is_some() {
+            assert_eq!(forks(&map), 0);
+        }
+    }
+
+    #[test]
+    fn rivers_may_merge() {
+        let river = RiverConfig {
+            count: 2,
+            direction: FlowDirection::South,
+            forks: Some(0),
+            merge: true,
+            ..RiverConfig::default()
+        };
+        let decks: [fn(&Map) -> Deck; 2] =
+            [|_| Deck::unlimited(), |map| Deck::finite(map.specs(), 2)];
+        for deck in decks {
+            // Joined by a fork tile, the rivers share the lake of one of them
+            let map = laid_with_fork(&river, deck, |map| forks(map) == 1);
+            assert_eq!(ends(&map, RiverEnd::Source).len(), 2);
+            assert_eq!(ends(&map, RiverEnd::Lake).len(), 1);
+            assert!(edges_match(&map));
+            assert!(flows_on(&map));
+        }
+    }
+
+    #[test]
+    fn an_impossible_length_is_refused() {
+        let mut map = Map::new(highres(), 4, 4);
+        let river = RiverConfig {
+            min_length: Some(8),
+            max_length: Some(4),
+            ..RiverConfig::default()
+        };
+        assert!(lay_rivers(&mut map, &mut Deck::unlimited(), &river, &mut Rng::new(5)).is_none());
+    }
+
+    #[test]
+    fn a_river_which_cannot_be_filled_around_is_removed() {
+        let specs = highres();
+        let cloister = specs.iter().position(|spec| spec.id == "cloister").unwrap() as u8;
+        let mut map = Map::new(specs, 6, 4);
+        let pinned = PlacedTile {
+            tile_spec: cloister,
+            rotation: 0,
+        };
+        map.pin(0, 0, pinned);
+        let mut deck = Deck::finite(map.specs(), 1);
+        let size = deck.size();
+        // Nothing fits the cells left, so every course is taken up again
+        let placed = place_river_tiles(
+            &mut map,
+            &mut deck,
+            &RiverConfig::default(),
+            &[],
+            &mut Rng::new(6),
+        );
+        assert_eq!(placed, Err(GenerateError::RiverPlacementFailed));
+        assert_eq!(map.tile(0, 0), Some(&pinned));
+        assert_eq!(map.tiles.iter().flatten().flatten().count(), 1);
+        assert_eq!(deck.size(), size);
+    }
+}
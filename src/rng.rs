@@ -0,0 +1,75 @@
+/// Deterministic pseudo random number generator used for every random choice
+/// made by the generator.
+///
+/// This is SplitMix64, it only uses integer arithmetic so the same seed gives
+/// the same sequence on every platform, including wasm.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in the range `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    /// Returns a value in the range `[0, n)`.
+    pub fn below(&mut self, n: usize) -> usize {
+        self.below_u64(n as u64) as usize
+    }
+
+    /// Returns a value in the range `[0, n)`, for ranges too large for a
+    /// `usize` on 32 bit targets.
+    pub fn below_u64(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_seed_gives_the_same_sequence() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+    }
+
+    #[test]
+    fn values_stay_in_range() {
+        let mut rng = Rng::new(7);
+        for n in 1..200 {
+            assert!(rng.below(n) < n);
+            assert!(rng.below_u64(n as u64 * 1_000_000_007) < n as u64 * 1_000_000_007);
+            let f = rng.next_f64();
+            assert!((0.0..1.0).contains(&f));
+        }
+        assert_eq!(rng.below(1), 0);
+    }
+
+    #[test]
+    fn below_covers_the_whole_range() {
+        let mut rng = Rng::new(3);
+        let mut seen = [false; 6];
+        for _ in 0..200 {
+            seen[rng.below(6)] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+}
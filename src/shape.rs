@@ -0,0 +1,272 @@
+use crate::error::GenerateError;
+use crate::rng::Rng;
+
+/// Passes of smoothing applied to the random cells of an island.
+const ISLAND_SMOOTHING: usize = 4;
+
+/// The outline of the map, marking which cells are laid with tiles. Cells
+/// outside it are left empty and treated as off the map.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shape {
+    /// Every cell.
+    #[default]
+    Rectangle,
+    /// An ellipse touching each side of the map.
+    Circle,
+    /// A hexagon with flat top and bottom edges, its corners touching the
+    /// sides of the map.
+    Hexagon,
+    /// A random island with a ragged coast.
+    Island,
+    /// A PNG image stretched over the map, cells whose centre falls on a
+    /// light opaque pixel are laid.
+    Png(Vec<u8>),
+}
+
+impl std::str::FromStr for Shape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rectangle" => Ok(Shape::Rectangle),
+            "circle" => Ok(Shape::Circle),
+            "hexagon" => Ok(Shape::Hexagon),
+            "island" => Ok(Shape::Island),
+            _ => Err(format!(
+                "unknown shape {}, expected rectangle, circle, hexagon or island",
+                s
+            )),
+        }
+    }
+}
+
+impl Shape {
+    /// Returns whether each cell of a `size_x` by `size_y` map is inside the
+    /// shape, row by row.
+    pub fn mask(
+        &self,
+        size_x: u32,
+        size_y: u32,
+        rng: &mut Rng,
+    ) -> Result<Vec<Vec<bool>>, GenerateError> {
+        let (w, h) = (size_x as i64, size_y as i64);
+        // Offsets of a cell's centre from the centre of the map, in units of
+        // half a cell
+        let offsets =
+            |x: usize, y: usize| ((2 * x as i64 + 1 - w).abs(), (2 * y as i64 + 1 - h).abs());
+        let mask = match self {
+            Shape::Rectangle => vec![vec![true; size_x as usize]; size_y as usize],
+            Shape::Circle => cells(size_x, size_y, |x, y| {
+                let (dx, dy) = offsets(x, y);
+                dx * dx * h * h + dy * dy * w * w <= w * w * h * h
+            }),
+            Shape::Hexagon => cells(size_x, size_y, |x, y| {
+                let (dx, dy) = offsets(x, y);
+                2 * dx * h <= 2 * w * h - dy * w
+            }),
+            Shape::Island => island(size_x, size_y, rng),
+            Shape::Png(bytes) => sample_png(bytes, size_x, size_y)
+                .map_err(|err| GenerateError::InvalidShape(err.to_string()))?
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|[luma, alpha]| luma >= 128 && alpha >= 128)
+                        .collect()
+                })
+                .collect(),
+        };
+        Ok(mask)
+    }
+}
+
+/// Stretches a PNG image over a `size_x` by `size_y` map and returns the
+/// lightness and opacity of the pixel at the centre of each cell, row by row.
+pub(crate) fn sample_png(
+    bytes: &[u8],
+    size_x: u32,
+    size_y: u32,
+) -> Result<Vec<Vec<[u8; 2]>>, image::ImageError> {
+    let image =
+        image::load_from_memory_with_format(bytes, image::ImageFormat::Png)?.to_luma_alpha8();
+    let (w, h) = (size_x as u64, size_y as u64);
+    Ok(cells(size_x, size_y, |x, y| {
+        let px = ((2 * x as u64 + 1) * image.width() as u64 / (2 * w)) as u32;
+        let py = ((2 * y as u64 + 1) * image.height() as u64 / (2 * h)) as u32;
+        image.get_pixel(px, py).0
+    }))
+}
+
+fn cells<T>(size_x: u32, size_y: u32, mut inside: impl FnMut(usize, usize) -> T) -> Vec<Vec<T>> {
+    (0..size_y as usize)
+        .map(|y| (0..size_x as usize).map(|x| inside(x, y)).collect())
+        .collect()
+}
+
+/// Scatters cells more thickly towards the centre of the map, smooths them
+/// into a coast and keeps the largest piece of land.
+fn island(size_x: u32, size_y: u32, rng: &mut Rng) -> Vec<Vec<bool>> {
+    let (w, h) = (size_x as i64, size_y as i64);
+    let mut mask = cells(size_x, size_y, |x, y| {
+        let (dx, dy) = ((2 * x as i64 + 1 - w).abs(), (2 * y as i64 + 1 - h).abs());
+        // Square of the distance from the centre, 1000 on the ellipse
+        // touching the sides of the map
+        let distance = (dx * dx * h * h + dy * dy * w * w) * 1000 / (w * w * h * h).max(1);
+        let chance = (1000 - distance).clamp(0, 700) * 100 / 700;
+        (rng.below(100) as i64) < chance
+    });
+
+    let (size_x, size_y) = (size_x as usize, size_y as usize);
+    for _ in 0..ISLAND_SMOOTHING {
+        mask = cells(size_x as u32, size_y as u32, |x, y| {
+            let rows = &mask[y.saturating_sub(1)..(y + 2).min(size_y)];
+            let land = rows
+                .iter()
+                .flat_map(|row| &row[x.saturating_sub(1)..(x + 2).min(size_x)])
+                .filter(|&&land| land)
+                .count();
+            land >= 5
+        });
+    }
+
+    // Label each piece of land and keep the largest
+    let mut pieces = vec![vec![None; size_x]; size_y];
+    let mut sizes = Vec::new();
+    for y in 0..size_y {
+        for x in 0..size_x {
+            if !mask[y][x] || pieces[y][x].is_some() {
+                continue;
+            }
+            let piece = sizes.len();
+            let mut size = 0;
+            let mut stack = vec![(x, y)];
+            pieces[y][x] = Some(piece);
+            while let Some((cx, cy)) = stack.pop() {
+                size += 1;
+                let neighbours = [
+                    (cx.wrapping_sub(1), cy),
+                    (cx + 1, cy),
+                    (cx, cy.wrapping_sub(1)),
+                    (cx, cy + 1),
+                ];
+                for (nx, ny) in neighbours {
+                    if nx < size_x && ny < size_y && mask[ny][nx] && pieces[ny][nx].is_none() {
+                        pieces[ny][nx] = Some(piece);
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            sizes.push(size);
+        }
+    }
+    let Some(largest) = (0..sizes.len()).max_by_key(|&piece| (sizes[piece], usize::MAX - piece))
+    else {
+        // Too small for any land to survive, keep the centre
+        let mut mask = vec![vec![false; size_x]; size_y];
+        if size_x > 0 && size_y > 0 {
+            mask[size_y / 2][size_x / 2] = true;
+        }
+        return mask;
+    };
+    pieces
+        .iter()
+        .map(|row| row.iter().map(|&piece| piece == Some(largest)).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask(shape: Shape, size_x: u32, size_y: u32) -> Vec<Vec<bool>> {
+        shape.mask(size_x, size_y, &mut Rng::new(1)).unwrap()
+    }
+
+    fn count(mask: &[Vec<bool>]) -> usize {
+        mask.iter().flatten().filter(|&&inside| inside).count()
+    }
+
+    /// Encodes a grayscale PNG of the given rows of pixels.
+    fn png(rows: &[&[u8]]) -> Vec<u8> {
+        let image = image::GrayImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            image::Luma([rows[y as usize][x as usize]])
+        });
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn a_rectangle_covers_the_map() {
+        assert_eq!(count(&mask(Shape::Rectangle, 7, 5)), 35);
+    }
+
+    #[test]
+    fn a_circle_leaves_out_the_corners() {
+        let circle = mask(Shape::Circle, 10, 10);
+        assert!(circle[5][5] && circle[0][5] && circle[5][0]);
+        assert!(!circle[0][0] && !circle[9][9] && !circle[0][9]);
+        for y in 0..10 {
+            for x in 0..10 {
+                assert_eq!(circle[y][x], circle[9 - y][x]);
+                assert_eq!(circle[y][x], circle[y][9 - x]);
+            }
+        }
+    }
+
+    #[test]
+    fn a_hexagon_has_flat_top_and_bottom() {
+        let hexagon = mask(Shape::Hexagon, 12, 10);
+        assert!(hexagon[0][6] && hexagon[9][6] && hexagon[5][0]);
+        assert!(!hexagon[0][0] && !hexagon[9][11]);
+    }
+
+    #[test]
+    fn an_island_is_one_piece() {
+        let island = mask(Shape::Island, 20, 16);
+        let land: Vec<(usize, usize)> = (0..16)
+            .flat_map(|y| (0..20).map(move |x| (x, y)))
+            .filter(|&(x, y)| island[y][x])
+            .collect();
+        assert!(land.len() > 20);
+        assert!(!island[0][0]);
+        let mut reached = vec![land[0]];
+        let mut stack = vec![land[0]];
+        while let Some((x, y)) = stack.pop() {
+            for (nx, ny) in [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ] {
+                if nx < 20 && ny < 16 && island[ny][nx] && !reached.contains(&(nx, ny)) {
+                    reached.push((nx, ny));
+                    stack.push((nx, ny));
+                }
+            }
+        }
+        assert_eq!(reached.len(), land.len());
+        assert_eq!(island, mask(Shape::Island, 20, 16));
+    }
+
+    #[test]
+    fn a_png_mask_keeps_light_cells() {
+        let bytes = png(&[&[255, 0], &[255, 255]]);
+        let mask = mask(Shape::Png(bytes), 4, 4);
+        assert_eq!(
+            mask,
+            vec![
+                vec![true, true, false, false],
+                vec![true, true, false, false],
+                vec![true, true, true, true],
+                vec![true, true, true, true],
+            ]
+        );
+    }
+
+    #[test]
+    fn a_broken_png_is_an_error() {
+        let result = Shape::Png(b"not a png".to_vec()).mask(4, 4, &mut Rng::new(0));
+        assert!(matches!(result, Err(GenerateError::InvalidShape(_))));
+    }
+}
@@ -0,0 +1,499 @@
+use crate::deck::Deck;
+use crate::density;
+use crate::error::GenerateError;
+use crate::map::{Map, PlacedTile};
+use crate::rng::Rng;
+
+/// Number of times the solver may undo a placement before giving up.
+const MAX_BACKTRACKS: usize = 1_000_000;
+
+/// Number of times a single search may backtrack before trying a larger area.
+const MAX_SEARCH_BACKTRACKS: usize = 10_000;
+
+/// A tile and rotation which may be placed in a cell, weighted by how often
+/// it should be chosen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    pub tile: u8,
+    pub rotation: u8,
+    pub weight: u32,
+}
+
+/// Expands a draw deck into every tile and rotation it can place, weighted so
+/// each tile is chosen in proportion to its count in the deck and each of its
+/// rotations equally often.
+pub fn candidates(map: &Map, deck: &[u8]) -> Vec<Candidate> {
+    let mut counts = vec![0u32; map.specs().len()];
+    for &tile in deck {
+        counts[tile as usize] += 1;
+    }
+    weighted_candidates(map, &counts)
+}
+
+/// Expands the tiles with a weight, indexed like `Map::specs`, into every
+/// rotation they can be placed in, each tile chosen in proportion to its
+/// weight and each of its rotations equally often.
+pub fn weighted_candidates(map: &Map, weights: &[u32]) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    for (tile, &count) in weights.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let rotations = match map.specs()[tile].can_be_rotated() {
+            true => 4,
+            false => 1,
+        };
+        for rotation in 0..rotations {
+            candidates.push(Candidate {
+                tile: tile as u8,
+                rotation,
+                weight: count.saturating_mul(4) / rotations as u32,
+            });
+        }
+    }
+    candidates
+}
+
+/// Returns the candidates which fit at the given position.
+pub fn fitting(map: &Map, candidates: &[Candidate], x: usize, y: usize) -> Vec<Candidate> {
+    candidates
+        .iter()
+        .filter(|c| map.can_be_placed(c.tile, x, y, c.rotation))
+        .copied()
+        .collect()
+}
+
+/// Removes and returns a random candidate, chosen in proportion to its weight.
+pub fn take_weighted(options: &mut Vec<Candidate>, rng: &mut Rng) -> Candidate {
+    take_by(options, rng, |c| c.weight as u64)
+}
+
+/// Removes and returns a random candidate for the cell at (`x`, `y`), chosen
+/// in proportion to the weight `deck` gives it and how well it suits the
+/// density of the cell. Candidates without a target in `deck` are only taken
+/// once no targeted candidate is left.
+pub fn take_from_deck(
+    options: &mut Vec<Candidate>,
+    map: &Map,
+    deck: &Deck,
+    x: usize,
+    y: usize,
+    rng: &mut Rng,
+) -> Candidate {
+    let targeted = options.iter().any(|c| deck.is_targeted(c.tile));
+    take_by(options, rng, |c| {
+        if targeted && !deck.is_targeted(c.tile) {
+            return 0;
+        }
+        density::biased(map, deck.weight(c), c.tile, x, y)
+    })
+}
+
+fn take_by(
+    options: &mut Vec<Candidate>,
+    rng: &mut Rng,
+    weight: impl Fn(&Candidate) -> u64,
+) -> Candidate {
+    let total: u64 = options.iter().map(&weight).sum();
+    let mut target = rng.below_u64(total);
+    for i in 0..options.len() {
+        let weight = weight(&options[i]);
+        if target < weight {
+            return options.swap_remove(i);
+        }
+        target -= weight;
+    }
+    unreachable!("target is always below the total weight")
+}
+
+/// Returns the first of `cells` which nothing fits next to the tiles already
+/// placed, such cells can never be filled.
+pub fn first_unfillable(
+    map: &Map,
+    candidates: &[Candidate],
+    cells: &[(usize, usize)],
+) -> Option<(usize, usize)> {
+    cells
+        .iter()
+        .find(|&&(x, y)| fitting(map, candidates, x, y).is_empty())
+        .copied()
+}
+
+/// Fills `cells` in order with candidates drawn from `deck` so that every edge
+/// matches its neighbours, tiles already on the map are kept.
+///
+/// After each placement the empty neighbours are checked to still have a
+/// candidate which fits. When nothing can be placed in a cell the earlier
+/// placements around it are cleared and solved again together, growing the
+/// cleared area until a fit is found.
+pub fn fill(
+    map: &mut Map,
+    candidates: &[Candidate],
+    deck: &mut Deck,
+    cells: &[(usize, usize)],
+    rng: &mut Rng,
+) -> Result<(), GenerateError> {
+    if let Some((x, y)) = first_unfillable(map, candidates, cells) {
+        return Err(GenerateError::NoTileFits { x, y });
+    }
+    let fill = Fill::new(map, candidates, cells);
+
+    let mut backtracks = 0;
+    let mut repairs = 0;
+    for (i, &(x, y)) in cells.iter().enumerate() {
+        let mut options = fitting(map, candidates, x, y);
+        if place_any(map, &fill, deck, &mut options, x, y, rng) {
+            continue;
+        }
+
+        repairs += 1;
+        let mut radius = 1;
+        loop {
+            let region: Vec<(usize, usize)> = cells[..=i]
+                .iter()
+                .filter(|(cx, cy)| cx.abs_diff(x) <= radius && cy.abs_diff(y) <= radius)
+                .copied()
+                .collect();
+            for &(cx, cy) in &region {
+                clear(map, deck, cx, cy);
+            }
+            if search(map, &fill, deck, &region, rng, &mut backtracks) {
+                break;
+            }
+            if backtracks > MAX_BACKTRACKS || region.len() == i + 1 {
+                return Err(GenerateError::FillFailed { backtracks });
+            }
+            radius += 1;
+        }
+    }
+    log::debug!(
+        "Filled {} cells with {} repairs and {} backtracks",
+        cells.len(),
+        repairs,
+        backtracks
+    );
+    Ok(())
+}
+
+/// The candidates a fill draws from and the cells it fills.
+struct Fill<'a> {
+    candidates: &'a [Candidate],
+    /// Whether each cell, row by row, is one of the cells being filled.
+    filling: Vec<bool>,
+    size_x: usize,
+}
+
+impl<'a> Fill<'a> {
+    fn new(map: &Map, candidates: &'a [Candidate], cells: &[(usize, usize)]) -> Self {
+        let size_x = map.size_x() as usize;
+        let mut filling = vec![false; size_x * map.size_y() as usize];
+        for &(x, y) in cells {
+            filling[y * size_x + x] = true;
+        }
+        Fill {
+            candidates,
+            filling,
+            size_x,
+        }
+    }
+
+    fn is_filling(&self, x: usize, y: usize) -> bool {
+        self.filling[y * self.size_x + x]
+    }
+}
+
+/// Tries the options for a cell in a random weighted order, placing the first
+/// which is left in the deck and leaves its empty neighbours with something
+/// that fits. Options which were tried are removed.
+fn place_any(
+    map: &mut Map,
+    fill: &Fill,
+    deck: &mut Deck,
+    options: &mut Vec<Candidate>,
+    x: usize,
+    y: usize,
+    rng: &mut Rng,
+) -> bool {
+    while !options.is_empty() {
+        let candidate = take_from_deck(options, map, deck, x, y, rng);
+        if !deck.has(candidate.tile) {
+            continue;
+        }
+        map.tiles[y][x] = Some(PlacedTile {
+            tile_spec: candidate.tile,
+            rotation: candidate.rotation,
+        });
+        deck.take(candidate.tile);
+        if neighbours_can_be_filled(map, fill, deck, x, y) {
+            return true;
+        }
+        clear(map, deck, x, y);
+    }
+    false
+}
+
+/// Removes the tile at the given position, returning it to the deck.
+pub(crate) fn clear(map: &mut Map, deck: &mut Deck, x: usize, y: usize) {
+    if let Some(tile) = map.tiles[y][x].take() {
+        deck.put_back(tile.tile_spec);
+    }
+}
+
+/// Depth first search filling the empty `cells` in order, backtracking over
+/// earlier choices when a cell has nothing left to try. Returns false, with
+/// all of `cells` left empty, if there is no solution or the search uses up
+/// its share of backtracks.
+fn search(
+    map: &mut Map,
+    fill: &Fill,
+    deck: &mut Deck,
+    cells: &[(usize, usize)],
+    rng: &mut Rng,
+    backtracks: &mut usize,
+) -> bool {
+    let budget = *backtracks + MAX_SEARCH_BACKTRACKS;
+    // Untried candidates for each cell filled so far
+    let mut options: Vec<Vec<Candidate>> = Vec::with_capacity(cells.len());
+    let mut i = 0;
+    while i < cells.len() {
+        let (x, y) = cells[i];
+        if options.len() == i {
+            options.push(fitting(map, fill.candidates, x, y));
+        }
+        if place_any(map, fill, deck, options.last_mut().unwrap(), x, y, rng) {
+            i += 1;
+            continue;
+        }
+
+        // Nothing fits here, undo the previous cell and try its next choice
+        options.pop();
+        *backtracks += 1;
+        if i == 0 || *backtracks > budget {
+            for &(cx, cy) in &cells[..i] {
+                clear(map, deck, cx, cy);
+            }
+            return false;
+        }
+        i -= 1;
+        let (prev_x, prev_y) = cells[i];
+        clear(map, deck, prev_x, prev_y);
+    }
+    true
+}
+
+/// Checks every empty neighbour of the given position which is being filled
+/// still has a candidate left in the deck which fits. Cells left empty, like
+/// those around an organic map, are not checked.
+fn neighbours_can_be_filled(map: &Map, fill: &Fill, deck: &Deck, x: usize, y: usize) -> bool {
+    (0..4).all(|i| {
+        let Some((new_x, new_y)) = map.neighbour(x, y, i) else {
+            return true;
+        };
+        map.tiles[new_y][new_x].is_some()
+            || !fill.is_filling(new_x, new_y)
+            || fill
+                .candidates
+                .iter()
+                .any(|c| deck.has(c.tile) && map.can_be_placed(c.tile, new_x, new_y, c.rotation))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::{base, edges_match, tile};
+    use crate::map::BorderPolicy;
+    use crate::tile::Feature;
+
+    fn cells(map: &Map) -> Vec<(usize, usize)> {
+        (0..map.size_y() as usize)
+            .flat_map(|y| (0..map.size_x() as usize).map(move |x| (x, y)))
+            .collect()
+    }
+
+    /// Returns each tile as many times as it comes in the box.
+    fn deck(map: &Map) -> Vec<u8> {
+        map.specs()
+            .iter()
+            .enumerate()
+            .flat_map(|(tile, spec)| std::iter::repeat_n(tile as u8, spec.count as usize))
+            .collect()
+    }
+
+    #[test]
+    fn fills_every_cell_with_matching_tiles() {
+        let mut map = Map::new(base(), 8, 6);
+        let candidates = candidates(&map, &deck(&map));
+        let cells = cells(&map);
+        fill(
+            &mut map,
+            &candidates,
+            &mut Deck::unlimited(),
+            &cells,
+            &mut Rng::new(1),
+        )
+        .unwrap();
+        assert!(map.tiles.iter().flatten().all(Option::is_some));
+        assert!(edges_match(&map));
+    }
+
+    #[test]
+    fn a_closed_border_is_respected() {
+        let mut map = Map::new(base(), 6, 5).with_border(BorderPolicy::Closed);
+        let candidates = candidates(&map, &deck(&map));
+        let cells = cells(&map);
+        fill(
+            &mut map,
+            &candidates,
+            &mut Deck::unlimited(),
+            &cells,
+            &mut Rng::new(2),
+        )
+        .unwrap();
+        assert!(edges_match(&map));
+    }
+
+    #[test]
+    fn a_cell_nothing_fits_is_reported() {
+        let specs = vec![
+            tile(
+                "cityn",
+                [Feature::City, Feature::None, Feature::None, Feature::None],
+            ),
+            tile(
+                "roadns",
+                [Feature::Road, Feature::None, Feature::Road, Feature::None],
+            ),
+        ];
+        let mut map = Map::new(specs, 2, 1);
+        // Only the city cap may be drawn, and it has no road to carry on
+        map.tiles[0][0] = Some(PlacedTile {
+            tile_spec: 1,
+            rotation: 1,
+        });
+        let candidates = candidates(&map, &[0]);
+        let result = fill(
+            &mut map,
+            &candidates,
+            &mut Deck::unlimited(),
+            &[(1, 0)],
+            &mut Rng::new(0),
+        );
+        assert_eq!(result, Err(GenerateError::NoTileFits { x: 1, y: 0 }));
+    }
+
+    #[test]
+    fn an_impossible_map_fails_to_fill() {
+        // Each empty cell is forced to turn the tile so its road faces the
+        // city of the other
+        let spec = tile(
+            "cityroad",
+            [Feature::None, Feature::City, Feature::None, Feature::Road],
+        );
+        let mut map = Map::new(vec![spec], 4, 1);
+        for x in [0, 3] {
+            map.tiles[0][x] = Some(PlacedTile {
+                tile_spec: 0,
+                rotation: 0,
+            });
+        }
+        let candidates = candidates(&map, &[0]);
+        let result = fill(
+            &mut map,
+            &candidates,
+            &mut Deck::unlimited(),
+            &[(1, 0), (2, 0)],
+            &mut Rng::new(0),
+        );
+        assert!(matches!(result, Err(GenerateError::FillFailed { .. })));
+    }
+
+    #[test]
+    fn a_finite_deck_is_drawn_from() {
+        let mut map = Map::new(base(), 4, 3);
+        let candidates = candidates(&map, &deck(&map));
+        let mut deck = Deck::finite(map.specs(), 1);
+        let cells = cells(&map);
+        fill(&mut map, &candidates, &mut deck, &cells, &mut Rng::new(3)).unwrap();
+        assert_eq!(deck.size(), Some(71 - 12));
+    }
+
+    #[test]
+    fn candidates_cover_every_rotation() {
+        let map = Map::new(base(), 1, 1);
+        let candidates = candidates(&map, &deck(&map));
+        let cloister = map
+            .specs()
+            .iter()
+            .position(|spec| spec.id == "cloister")
+            .unwrap();
+        let roadns = map
+            .specs()
+            .iter()
+            .position(|spec| spec.id == "roadns")
+            .unwrap();
+        let of = |tile: usize| {
+            candidates
+                .iter()
+                .filter(|c| c.tile as usize == tile)
+                .count()
+        };
+        assert_eq!(of(cloister), 1);
+        assert_eq!(of(roadns), 4);
+        // Every rotation together weighs as much as the tile's count
+        let weight = |tile: usize| -> u32 {
+            candidates
+                .iter()
+                .filter(|c| c.tile as usize == tile)
+                .map(|c| c.weight)
+                .sum()
+        };
+        assert_eq!(weight(cloister), 4 * 4);
+        assert_eq!(weight(roadns), 8 * 4);
+    }
+
+    #[test]
+    fn unweighted_candidates_are_never_taken() {
+        let mut rng = Rng::new(9);
+        for _ in 0..50 {
+            let mut options = vec![
+                Candidate {
+                    tile: 0,
+                    rotation: 0,
+                    weight: 0,
+                },
+                Candidate {
+                    tile: 1,
+                    rotation: 0,
+                    weight: 5,
+                },
+            ];
+            assert_eq!(take_weighted(&mut options, &mut rng).tile, 1);
+            assert_eq!(options.len(), 1);
+        }
+    }
+
+    #[test]
+    fn untargeted_candidates_wait_for_targeted_ones() {
+        let map = Map::new(base(), 1, 1);
+        let deck = Deck::unlimited().with_targets(vec![1, 0]);
+        let mut rng = Rng::new(4);
+        for _ in 0..50 {
+            let mut options: Vec<Candidate> = (0..2)
+                .map(|tile| Candidate {
+                    tile,
+                    rotation: 0,
+                    weight: 4,
+                })
+                .collect();
+            assert_eq!(
+                take_from_deck(&mut options, &map, &deck, 0, 0, &mut rng).tile,
+                0
+            );
+            assert_eq!(
+                take_from_deck(&mut options, &map, &deck, 0, 0, &mut rng).tile,
+                1
+            );
+        }
+    }
+}
@@ -0,0 +1,410 @@
+/// The feature running across one edge of a tile.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    None,
+    City,
+    Road,
+    River,
+}
+
+/// The game or expansion a tile belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expansion {
+    Base,
+    River,
+}
+
+impl Expansion {
+    /// Name used for the expansion in catalogs and tile art sets.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Expansion::Base => "base",
+            Expansion::River => "river",
+        }
+    }
+}
+
+/// Marks a river tile which starts or ends the river, having a single river
+/// edge.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiverEnd {
+    /// The spring the river rises from.
+    Source,
+    /// The lake the river flows into.
+    Lake,
+}
+
+/// One of the twelve segments around the edge of a tile, named by compass
+/// point and ordered clockwise from the north west corner.
+///
+/// Each edge is split into the half before its midpoint, the midpoint where a
+/// road or river crosses it and the half after, so `Nnw`, `N` and `Nne` make
+/// up the north edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Segment {
+    Nnw,
+    N,
+    Nne,
+    Ene,
+    E,
+    Ese,
+    Sse,
+    S,
+    Ssw,
+    Wsw,
+    W,
+    Wnw,
+}
+
+impl Segment {
+    pub const ALL: [Segment; 12] = [
+        Segment::Nnw,
+        Segment::N,
+        Segment::Nne,
+        Segment::Ene,
+        Segment::E,
+        Segment::Ese,
+        Segment::Sse,
+        Segment::S,
+        Segment::Ssw,
+        Segment::Wsw,
+        Segment::W,
+        Segment::Wnw,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn from_index(index: usize) -> Segment {
+        Segment::ALL[index % 12]
+    }
+
+    /// Returns the edge the segment is on, north, east, south or west.
+    pub fn edge(self) -> usize {
+        self.index() / 3
+    }
+
+    /// Returns true for the segment at the middle of an edge.
+    pub fn is_midpoint(self) -> bool {
+        self.index() % 3 == 1
+    }
+
+    /// Returns where the segment ends up once the tile is turned by
+    /// `rotation`, matching `TileSpec::edge`.
+    pub fn rotated(self, rotation: u8) -> Segment {
+        Segment::from_index(self.index() + 12 - 3 * (rotation as usize % 4))
+    }
+
+    /// Returns the segment of the neighbouring tile which this one touches
+    /// across its edge.
+    pub fn opposite(self) -> Segment {
+        let edge = (self.edge() + 2) % 4;
+        Segment::from_index(edge * 3 + 2 - self.index() % 3)
+    }
+}
+
+/// The kinds of connected area a tile is divided into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    City,
+    Road,
+    River,
+    Field,
+}
+
+/// One city, road, river or field on a tile and the segments of the tile's
+/// edge it touches. Areas on different segments of a tile are only
+/// connected if they are in the same region.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(from = "CatalogRegion")]
+pub struct Region {
+    pub kind: RegionKind,
+    pub segments: Vec<Segment>,
+}
+
+/// A region as written in a catalog, e.g. `{ "city": ["n", "w"] }`.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CatalogRegion {
+    City(Vec<Segment>),
+    Road(Vec<Segment>),
+    River(Vec<Segment>),
+    Field(Vec<Segment>),
+}
+
+impl From<CatalogRegion> for Region {
+    fn from(region: CatalogRegion) -> Self {
+        let (kind, segments) = match region {
+            CatalogRegion::City(segments) => (RegionKind::City, segments),
+            CatalogRegion::Road(segments) => (RegionKind::Road, segments),
+            CatalogRegion::River(segments) => (RegionKind::River, segments),
+            CatalogRegion::Field(segments) => (RegionKind::Field, segments),
+        };
+        Region { kind, segments }
+    }
+}
+
+/// Describes one kind of tile, independent of how its art is drawn.
+///
+/// `id` is an opaque identifier for the tile and `art` is the key front ends
+/// use to look up its image within the expansion's art, edge features are
+/// ordered north, east, south, west. `regions` says which parts of the tile's
+/// edge are joined by the same city, road, river or field.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct TileSpec {
+    pub id: String,
+    pub expansion: Expansion,
+    pub art: String,
+    #[serde(default)]
+    pub cloister: bool,
+    #[serde(default, rename = "shield")]
+    pub sheild: bool,
+    #[serde(rename = "edges")]
+    pub edge_features: [Feature; 4],
+    #[serde(default)]
+    pub river_end: Option<RiverEnd>,
+    #[serde(default)]
+    pub regions: Vec<Region>,
+    pub count: i32,
+}
+
+impl TileSpec {
+    /// Returns the feature on the given edge (north, east, south, west) once
+    /// the tile is turned by `rotation`.
+    pub fn edge(&self, direction: usize, rotation: u8) -> Feature {
+        self.edge_features[(direction + rotation as usize) % 4]
+    }
+
+    /// Returns the regions on the tile, before rotation, with each segment
+    /// given in full.
+    ///
+    /// A city edge, or an edge with no feature, lies in a single region so
+    /// the catalog may name it by its midpoint alone. Tiles which don't
+    /// declare their regions have every city joined, roads and rivers joined
+    /// when there are exactly two of them, and fields divided by the features
+    /// which cross the tile or meet at a junction.
+    pub fn connected_regions(&self) -> Vec<Region> {
+        if self.regions.is_empty() {
+            return self.default_regions();
+        }
+        self.regions
+            .iter()
+            .map(|region| {
+                let mut segments = Vec::new();
+                for &segment in &region.segments {
+                    let whole_edge = segment.is_midpoint()
+                        && matches!(
+                            self.edge_features[segment.edge()],
+                            Feature::City | Feature::None
+                        );
+                    let expanded = match whole_edge {
+                        true => (segment.index() - 1..=segment.index() + 1)
+                            .map(Segment::from_index)
+                            .collect(),
+                        false => vec![segment],
+                    };
+                    for segment in expanded {
+                        if !segments.contains(&segment) {
+                            segments.push(segment);
+                        }
+                    }
+                }
+                Region {
+                    kind: region.kind,
+                    segments,
+                }
+            })
+            .collect()
+    }
+
+    fn default_regions(&self) -> Vec<Region> {
+        let mut regions = Vec::new();
+        let cities: Vec<Segment> = Segment::ALL
+            .into_iter()
+            .filter(|s| self.edge_features[s.edge()] == Feature::City)
+            .collect();
+        if !cities.is_empty() {
+            regions.push(Region {
+                kind: RegionKind::City,
+                segments: cities,
+            });
+        }
+        for (feature, kind) in [
+            (Feature::Road, RegionKind::Road),
+            (Feature::River, RegionKind::River),
+        ] {
+            let ends: Vec<Segment> = Segment::ALL
+                .into_iter()
+                .filter(|s| s.is_midpoint() && self.edge_features[s.edge()] == feature)
+                .collect();
+            if ends.len() == 2 {
+                regions.push(Region {
+                    kind,
+                    segments: ends,
+                });
+            } else {
+                regions.extend(ends.into_iter().map(|end| Region {
+                    kind,
+                    segments: vec![end],
+                }));
+            }
+        }
+
+        // Fields are divided by features which join two parts of the edge,
+        // and by three or more roads or rivers meeting in a junction
+        let mut cuts: Vec<Vec<Segment>> = regions
+            .iter()
+            .filter(|region| region.segments.len() > 1)
+            .map(|region| region.segments.clone())
+            .collect();
+        let ends: Vec<Segment> = regions
+            .iter()
+            .filter(|region| region.kind != RegionKind::City && region.segments.len() == 1)
+            .map(|region| region.segments[0])
+            .collect();
+        if ends.len() >= 3 {
+            cuts.push(ends);
+        }
+        let is_field = |s: Segment| match self.edge_features[s.edge()] {
+            Feature::None => true,
+            Feature::City => false,
+            Feature::Road | Feature::River => !s.is_midpoint(),
+        };
+        let mut fields: Vec<Vec<Segment>> = Vec::new();
+        for segment in Segment::ALL.into_iter().filter(|&s| is_field(s)) {
+            let joined = fields
+                .iter_mut()
+                .find(|field| !cuts.iter().any(|cut| separates(cut, field[0], segment)));
+            match joined {
+                Some(field) => field.push(segment),
+                None => fields.push(vec![segment]),
+            }
+        }
+        regions.extend(fields.into_iter().map(|segments| Region {
+            kind: RegionKind::Field,
+            segments,
+        }));
+        regions
+    }
+
+    pub fn can_be_rotated(&self) -> bool {
+        !self
+            .edge_features
+            .iter()
+            .all(|&f| f == self.edge_features[0])
+    }
+}
+
+/// Returns true if a feature touching the edge at each of `cut` lies between
+/// segments `a` and `b` whichever way round the tile you go.
+fn separates(cut: &[Segment], a: Segment, b: Segment) -> bool {
+    let (low, high) = (a.index().min(b.index()), a.index().max(b.index()));
+    let between = |s: &Segment| low < s.index() && s.index() < high;
+    cut.iter().any(between) && !cut.iter().all(between)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::{highres, tile};
+
+    #[test]
+    fn four_turns_bring_a_segment_back() {
+        for segment in Segment::ALL {
+            assert_eq!(segment.rotated(0), segment);
+            assert_eq!(segment.rotated(4), segment);
+            assert_eq!(segment.rotated(1).rotated(3), segment);
+            assert_eq!(segment.rotated(2).rotated(2), segment);
+        }
+    }
+
+    #[test]
+    fn segments_turn_with_the_edges() {
+        let cityn = tile(
+            "cityn",
+            [Feature::City, Feature::None, Feature::None, Feature::None],
+        );
+        for rotation in 0..4 {
+            let city = Segment::N.rotated(rotation).edge();
+            for edge in 0..4 {
+                let expected = match edge == city {
+                    true => Feature::City,
+                    false => Feature::None,
+                };
+                assert_eq!(cityn.edge(edge, rotation), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn opposite_segments_face_each_other() {
+        assert_eq!(Segment::N.opposite(), Segment::S);
+        assert_eq!(Segment::Nne.opposite(), Segment::Sse);
+        assert_eq!(Segment::Wnw.opposite(), Segment::Ene);
+        for segment in Segment::ALL {
+            assert_eq!(segment.opposite().opposite(), segment);
+            assert_eq!(segment.opposite().edge(), (segment.edge() + 2) % 4);
+            assert_eq!(segment.opposite().is_midpoint(), segment.is_midpoint());
+        }
+    }
+
+    #[test]
+    fn a_straight_road_divides_the_field() {
+        let roadns = tile(
+            "roadns",
+            [Feature::Road, Feature::None, Feature::Road, Feature::None],
+        );
+        let regions = roadns.connected_regions();
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions[0].kind, RegionKind::Road);
+        assert_eq!(regions[0].segments, vec![Segment::N, Segment::S]);
+        let fields: Vec<&Region> = regions
+            .iter()
+            .filter(|region| region.kind == RegionKind::Field)
+            .collect();
+        assert_eq!(fields.len(), 2);
+        assert!(fields.iter().all(|field| field.segments.len() == 5));
+        assert!(fields[0].segments.contains(&Segment::Nnw));
+        assert!(fields[1].segments.contains(&Segment::Nne));
+    }
+
+    #[test]
+    fn a_crossroads_ends_every_road() {
+        let roads = tile("roadnesw", [Feature::Road; 4]).connected_regions();
+        let count = |kind| roads.iter().filter(|region| region.kind == kind).count();
+        assert_eq!(count(RegionKind::Road), 4);
+        assert_eq!(count(RegionKind::Field), 4);
+        assert!(roads
+            .iter()
+            .filter(|region| region.kind == RegionKind::Field)
+            .all(|field| field.segments.len() == 2));
+    }
+
+    #[test]
+    fn catalog_midpoints_name_whole_city_edges() {
+        let specs = highres();
+        let cityn = specs.iter().find(|spec| spec.id == "cityn").unwrap();
+        let city = cityn
+            .connected_regions()
+            .into_iter()
+            .find(|region| region.kind == RegionKind::City)
+            .unwrap();
+        assert_eq!(city.segments, vec![Segment::Nnw, Segment::N, Segment::Nne]);
+    }
+
+    #[test]
+    fn uniform_tiles_are_not_rotated() {
+        assert!(!tile("cloister", [Feature::None; 4]).can_be_rotated());
+        assert!(tile(
+            "roadns",
+            [Feature::Road, Feature::None, Feature::Road, Feature::None]
+        )
+        .can_be_rotated());
+    }
+}
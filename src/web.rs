@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use web_sys::HtmlImageElement;
+
+use crate::catalog::{Catalog, MissingArtPolicy};
+use crate::constraints::Unsatisfied;
+use crate::error::GenerateError;
+use crate::features::MapFeature;
+use crate::generator::{GeneratorConfig, Selection, TileFrequency};
+use crate::layout::{LayoutError, OutputSize};
+use crate::map::Map;
+use crate::placeholder::{
+    css_colour, CITY_COLOUR, CITY_DEPTH, CLOISTER_COLOUR, CLOISTER_SIZE, FIELD_COLOUR,
+    RIVER_COLOUR, RIVER_WIDTH, ROAD_COLOUR, ROAD_WIDTH,
+};
+use crate::tile::{Expansion, Feature, TileSpec};
+
+/// Errors which can stop a map being rendered in the browser.
+#[derive(Debug)]
+pub enum RenderError {
+    /// The tile art object passed from JS could not be read.
+    InvalidTileArt(String),
+    /// The render options passed from JS could not be read.
+    InvalidOptions(String),
+    /// The catalog uses an expansion which has no art.
+    MissingTileset(Expansion),
+    /// The art URL for the given key could not be used.
+    BadArtUrl(String),
+    /// The image for the given art key failed to load.
+    ImageLoadFailed(String),
+    /// The image for the given art key took too long to load.
+    ImageLoadTimedOut(String),
+    /// There is no `#canvas` element on the page.
+    CanvasNotFound,
+    /// A call on the canvas or its 2D context failed.
+    CanvasError(String),
+    /// The requested size gives no usable layout.
+    InvalidLayout(LayoutError),
+    /// The map itself could not be generated.
+    GenerationFailed(GenerateError),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::InvalidTileArt(msg) => write!(f, "invalid tile art: {}", msg),
+            RenderError::InvalidOptions(msg) => write!(f, "invalid render options: {}", msg),
+            RenderError::MissingTileset(expansion) => {
+                write!(
+                    f,
+                    "tile art for the {} tileset is missing",
+                    expansion.name()
+                )
+            }
+            RenderError::BadArtUrl(key) => write!(f, "bad art URL for tile art {}", key),
+            RenderError::ImageLoadFailed(key) => {
+                write!(f, "image for tile art {} failed to load", key)
+            }
+            RenderError::ImageLoadTimedOut(key) => {
+                write!(f, "image for tile art {} timed out while loading", key)
+            }
+            RenderError::CanvasNotFound => write!(f, "canvas element #canvas not found"),
+            RenderError::CanvasError(msg) => write!(f, "canvas error: {}", msg),
+            RenderError::InvalidLayout(err) => write!(f, "invalid map size: {}", err),
+            RenderError::GenerationFailed(err) => write!(f, "map generation failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<LayoutError> for RenderError {
+    fn from(err: LayoutError) -> Self {
+        RenderError::InvalidLayout(err)
+    }
+}
+
+impl From<GenerateError> for RenderError {
+    fn from(err: GenerateError) -> Self {
+        RenderError::GenerationFailed(err)
+    }
+}
+
+/// Converts the error from a failed canvas call.
+pub fn canvas_error(err: JsValue) -> RenderError {
+    RenderError::CanvasError(format!("{:?}", err))
+}
+
+/// Options for rendering passed in from JS, every field is optional.
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct RenderOptions {
+    /// What to do when a tile's image fails to load.
+    pub missing_art: MissingArtPolicy,
+    /// How long to wait for each image before treating it as failed.
+    pub image_timeout_ms: u32,
+    /// Size of the canvas and of each tile.
+    pub size: OutputSize,
+    /// How the map is generated.
+    pub generator: GeneratorConfig,
+    /// Parts of the map to clear and lay again after it is generated, in
+    /// order.
+    pub regenerate: Vec<Regeneration>,
+}
+
+/// Cells of the generated map to lay again with another seed.
+#[derive(serde::Deserialize)]
+pub struct Regeneration {
+    pub cells: Selection,
+    pub seed: u64,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            missing_art: MissingArtPolicy::Fail,
+            image_timeout_ms: 10000,
+            size: OutputSize::default(),
+            generator: GeneratorConfig::default(),
+            regenerate: Vec::new(),
+        }
+    }
+}
+
+impl RenderOptions {
+    pub fn from_js(options: JsValue) -> Result<Self, RenderError> {
+        if options.is_undefined() || options.is_null() {
+            return Ok(RenderOptions::default());
+        }
+        serde_wasm_bindgen::from_value(options)
+            .map_err(|err| RenderError::InvalidOptions(err.to_string()))
+    }
+}
+
+/// Summary of a rendered map returned to JS.
+#[derive(serde::Serialize)]
+pub struct RenderReport {
+    /// Seed the map was generated from.
+    pub seed: u64,
+    /// Art keys of the images which failed to load.
+    pub failed_art: Vec<String>,
+    /// Number of tiles across and down the generated map.
+    pub columns: u32,
+    pub rows: u32,
+    /// Every city, road, river, field and cloister on the map.
+    pub features: Vec<MapFeature>,
+    /// Each base game tile's target and actual share of the map.
+    pub tiles: Vec<TileFrequency>,
+    /// Each feature constraint the map does not meet.
+    pub unsatisfied: Vec<Unsatisfied>,
+}
+
+impl RenderReport {
+    pub fn to_js(&self) -> Result<JsValue, serde_wasm_bindgen::Error> {
+        let serializer =
+            serde_wasm_bindgen::Serializer::new().serialize_large_number_types_as_bigints(true);
+        self.serialize(&serializer)
+    }
+}
+
+/// Tile art passed in from JS, the catalog describes the tiles and the art
+/// for each expansion maps a tile's art key to the URL of its image.
+#[derive(serde::Deserialize)]
+pub struct TileArt {
+    pub catalog: Catalog,
+    pub base: Option<HashMap<String, String>>,
+    pub river: Option<HashMap<String, String>>,
+}
+
+impl TileArt {
+    /// Returns the art URL for the given tile, if the tileset has art for it.
+    pub fn url(&self, spec: &TileSpec) -> Result<Option<&String>, RenderError> {
+        let art = match spec.expansion {
+            Expansion::Base => self.base.as_ref(),
+            Expansion::River => self.river.as_ref(),
+        };
+        let art = art.ok_or(RenderError::MissingTileset(spec.expansion))?;
+        Ok(art.get(&spec.art))
+    }
+}
+
+/// Picks a fresh seed using `Math.random()`, for when the caller did not
+/// supply one.
+pub fn random_seed() -> u64 {
+    let high = (js_sys::Math::random() * 4294967296.0) as u64;
+    let low = (js_sys::Math::random() * 4294967296.0) as u64;
+    (high << 32) | low
+}
+
+/// Keeps only the tiles which have art available, paired with their loaded
+/// images.
+pub fn load_tile_images(
+    tileart: &TileArt,
+) -> Result<(Vec<TileSpec>, Vec<HtmlImageElement>), RenderError> {
+    let mut tiles = Vec::new();
+    let mut images = Vec::new();
+    for spec in &tileart.catalog.tiles {
+        if let Some(url) = tileart.url(spec)? {
+            let image = load_image(url).ok_or_else(|| RenderError::BadArtUrl(spec.art.clone()))?;
+            images.push(image);
+            tiles.push(spec.clone());
+        }
+    }
+    Ok((tiles, images))
+}
+
+fn load_image(url: &str) -> Option<HtmlImageElement> {
+    if url.is_empty() {
+        return None;
+    }
+    let img = HtmlImageElement::new().ok()?;
+    img.set_src(url);
+    Some(img)
+}
+
+/// Tile art once loading has finished, `images` holds the art for each entry
+/// in `tiles` or `None` where a placeholder should be drawn instead.
+pub struct LoadedArt {
+    pub tiles: Vec<TileSpec>,
+    pub images: Vec<Option<HtmlImageElement>>,
+    /// Art keys of the images which failed to load.
+    pub failed: Vec<String>,
+}
+
+/// Waits for all images to finish loading, `tiles` holds the spec each image
+/// was loaded for. Images which fail or time out are handled according to
+/// the `missing_art` policy in `options`.
+pub async fn wait_for_images(
+    tiles: Vec<TileSpec>,
+    images: Vec<HtmlImageElement>,
+    options: &RenderOptions,
+) -> Result<LoadedArt, RenderError> {
+    let mut loaded = LoadedArt {
+        tiles: Vec::new(),
+        images: Vec::new(),
+        failed: Vec::new(),
+    };
+    for (tile, image) in tiles.into_iter().zip(images) {
+        match wait_for_image(&image, options.image_timeout_ms).await {
+            Ok(()) => {
+                loaded.tiles.push(tile);
+                loaded.images.push(Some(image));
+                continue;
+            }
+            Err(failure) => {
+                let err = match failure {
+                    ImageFailure::Error => RenderError::ImageLoadFailed(tile.art.clone()),
+                    ImageFailure::Timeout => RenderError::ImageLoadTimedOut(tile.art.clone()),
+                };
+                match options.missing_art {
+                    MissingArtPolicy::Fail => return Err(err),
+                    MissingArtPolicy::Skip => {
+                        log::warn!("{}, removing tile {} from the deck", err, tile.id);
+                    }
+                    MissingArtPolicy::Placeholder => {
+                        log::warn!("{}, drawing a placeholder for tile {}", err, tile.id);
+                        loaded.tiles.push(tile.clone());
+                        loaded.images.push(None);
+                    }
+                }
+                loaded.failed.push(tile.art);
+            }
+        }
+    }
+    Ok(loaded)
+}
+
+enum ImageFailure {
+    Error,
+    Timeout,
+}
+
+/// Waits for a single image to load, failing if it errors or does not load
+/// within `timeout_ms`.
+async fn wait_for_image(image: &HtmlImageElement, timeout_ms: u32) -> Result<(), ImageFailure> {
+    if image.complete() {
+        // Images which failed to load are also complete but have no size
+        return match image.natural_width() > 0 {
+            true => Ok(()),
+            false => Err(ImageFailure::Error),
+        };
+    }
+
+    let window = web_sys::window();
+    let mut timeout_handle = None;
+    let img_promise = js_sys::Promise::new(&mut |resolve, reject| {
+        let onload = Closure::once_into_js(move || {
+            resolve.call0(&JsValue::NULL).unwrap();
+        });
+        image.set_onload(Some(onload.unchecked_ref()));
+
+        let reject_on_error = reject.clone();
+        let onerror = Closure::once_into_js(move || {
+            reject_on_error
+                .call1(&JsValue::NULL, &JsValue::FALSE)
+                .unwrap();
+        });
+        image.set_onerror(Some(onerror.unchecked_ref()));
+
+        if let Some(window) = &window {
+            let ontimeout = Closure::once_into_js(move || {
+                reject.call1(&JsValue::NULL, &JsValue::TRUE).unwrap();
+            });
+            timeout_handle = window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(
+                    ontimeout.unchecked_ref(),
+                    timeout_ms as i32,
+                )
+                .ok();
+        }
+    });
+
+    let result = wasm_bindgen_futures::JsFuture::from(img_promise).await;
+    if let (Some(window), Some(handle)) = (&window, timeout_handle) {
+        window.clear_timeout_with_handle(handle);
+    }
+    result
+        .map(|_| ())
+        .map_err(|timed_out| match timed_out.is_truthy() {
+            true => ImageFailure::Timeout,
+            false => ImageFailure::Error,
+        })
+}
+
+/// Draws the map, `images` holds the art for each entry in `Map::specs`.
+pub fn draw_map(
+    context: &web_sys::CanvasRenderingContext2d,
+    map: &Map,
+    images: &[Option<HtmlImageElement>],
+    tile_size: f64,
+) -> Result<(), RenderError> {
+    for y in 0..map.size_y() {
+        let pos_y = y as f64 * tile_size;
+        for x in 0..map.size_x() {
+            if let Some(tile) = map.tile(x as usize, y as usize) {
+                let pos_x = x as f64 * tile_size;
+                draw_tile(
+                    context,
+                    &map.specs()[tile.tile_spec as usize],
+                    images[tile.tile_spec as usize].as_ref(),
+                    pos_x,
+                    pos_y,
+                    tile.rotation,
+                    tile_size,
+                )
+                .map_err(canvas_error)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn draw_tile(
+    context: &web_sys::CanvasRenderingContext2d,
+    spec: &TileSpec,
+    art: Option<&HtmlImageElement>,
+    x: f64,
+    y: f64,
+    rotation: u8,
+    tile_size: f64,
+) -> Result<(), JsValue> {
+    let half_tile_size = tile_size / 2.0;
+    context.save();
+    context.translate(x + half_tile_size, y + half_tile_size)?;
+    for _ in 0..rotation {
+        context.rotate(-std::f64::consts::FRAC_PI_2)?;
+    }
+    match art {
+        Some(art) => context.draw_image_with_html_image_element_and_dw_and_dh(
+            art,
+            -half_tile_size,
+            -half_tile_size,
+            tile_size,
+            tile_size,
+        )?,
+        None => draw_placeholder(context, spec, tile_size)?,
+    }
+    context.restore();
+    Ok(())
+}
+
+/// Draws a simple stand in for a tile's art, centred on the origin.
+fn draw_placeholder(
+    context: &web_sys::CanvasRenderingContext2d,
+    spec: &TileSpec,
+    tile_size: f64,
+) -> Result<(), JsValue> {
+    let half_tile_size = tile_size / 2.0;
+    context.set_fill_style_str(&css_colour(FIELD_COLOUR));
+    context.fill_rect(-half_tile_size, -half_tile_size, tile_size, tile_size);
+    for feature in spec.edge_features {
+        // Each edge is drawn as if it were the north edge, then turned clockwise
+        match feature {
+            Feature::City => {
+                let inner = half_tile_size * (1.0 - CITY_DEPTH);
+                context.set_fill_style_str(&css_colour(CITY_COLOUR));
+                context.begin_path();
+                context.move_to(-half_tile_size, -half_tile_size);
+                context.line_to(half_tile_size, -half_tile_size);
+                context.line_to(inner, -inner);
+                context.line_to(-inner, -inner);
+                context.close_path();
+                context.fill();
+            }
+            Feature::Road | Feature::River => {
+                let (colour, width) = match feature {
+                    Feature::Road => (ROAD_COLOUR, ROAD_WIDTH),
+                    _ => (RIVER_COLOUR, RIVER_WIDTH),
+                };
+                let half_width = half_tile_size * width;
+                context.set_fill_style_str(&css_colour(colour));
+                context.fill_rect(
+                    -half_width,
+                    -half_tile_size,
+                    2.0 * half_width,
+                    half_tile_size,
+                );
+            }
+            Feature::None => {}
+        }
+        context.rotate(std::f64::consts::FRAC_PI_2)?;
+    }
+    if spec.cloister {
+        let half_size = half_tile_size * CLOISTER_SIZE;
+        context.set_fill_style_str(&css_colour(CLOISTER_COLOUR));
+        context.fill_rect(-half_size, -half_size, 2.0 * half_size, 2.0 * half_size);
+    }
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use crate::error::GenerateError;
+use crate::tile::{Expansion, Feature, TileSpec};
+
+/// Most a tile can be weighted, in hundredths of a tile. Heavier tiles are
+/// held to this, keeping the weights of every candidate summed well within
+/// range however they are steered and biased.
+pub const MAX_TILE_WEIGHT: u32 = 1 << 20;
+
+/// A named set of feature weights giving maps a particular character.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Every tile as often as it comes in the box.
+    #[default]
+    Balanced,
+    /// Large sprawling cities.
+    DenseCities,
+    /// Open fields with scattered cloisters and few cities.
+    Countryside,
+    /// Roads criss-crossing the map.
+    RoadNetwork,
+    /// Cloisters dotted across quiet fields.
+    Monastic,
+}
+
+impl Preset {
+    /// Returns the preset's percentage weights for city, road and field
+    /// edges and for cloisters.
+    fn features(&self) -> [u32; 4] {
+        match self {
+            Preset::Balanced => [100, 100, 100, 100],
+            Preset::DenseCities => [500, 100, 40, 40],
+            Preset::Countryside => [30, 70, 200, 150],
+            Preset::RoadNetwork => [60, 300, 70, 50],
+            Preset::Monastic => [70, 100, 120, 500],
+        }
+    }
+}
+
+impl std::str::FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "balanced" => Ok(Preset::Balanced),
+            "dense-cities" => Ok(Preset::DenseCities),
+            "countryside" => Ok(Preset::Countryside),
+            "road-network" => Ok(Preset::RoadNetwork),
+            "monastic" => Ok(Preset::Monastic),
+            _ => Err(format!(
+                "unknown preset {}, expected balanced, dense-cities, countryside, road-network or monastic",
+                s
+            )),
+        }
+    }
+}
+
+/// How likely each base game tile is to be chosen, separate from how many
+/// come in the box. Weights are percentages, 100 leaving a tile as likely as
+/// its count makes it and 0 never choosing it.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Weights {
+    /// Feature weights used for any not given below.
+    pub preset: Preset,
+    /// Weight of city edges.
+    pub city: Option<u32>,
+    /// Weight of road edges.
+    pub road: Option<u32>,
+    /// Weight of edges with nothing but field.
+    pub field: Option<u32>,
+    /// Weight of tiles with a cloister.
+    pub cloister: Option<u32>,
+    /// Weight of each tile by id, on top of its features.
+    pub tiles: HashMap<String, u32>,
+}
+
+impl Weights {
+    /// Fails naming the first tile weighted which is not in `specs`, or if
+    /// every base game tile is weighted 0.
+    pub fn check(&self, specs: &[TileSpec]) -> Result<(), GenerateError> {
+        let mut ids: Vec<&String> = self.tiles.keys().collect();
+        ids.sort();
+        if let Some(id) = ids
+            .into_iter()
+            .find(|&id| !specs.iter().any(|spec| &spec.id == id))
+        {
+            return Err(GenerateError::UnknownTile { id: id.clone() });
+        }
+        let weighted = specs
+            .iter()
+            .zip(self.tile_weights(specs))
+            .any(|(spec, weight)| spec.expansion == Expansion::Base && weight > 0);
+        match weighted {
+            true => Ok(()),
+            false => Err(GenerateError::NoTileWeighted),
+        }
+    }
+
+    /// Returns the weight of each tile in `specs` in hundredths of a tile: its
+    /// count in the box scaled by the tile's own weight, the mean weight of
+    /// its four edges and the cloister weight if it has one, at most
+    /// `MAX_TILE_WEIGHT`.
+    pub fn tile_weights(&self, specs: &[TileSpec]) -> Vec<u32> {
+        let [city, road, field, cloister] = self.preset.features();
+        let city = self.city.unwrap_or(city) as u64;
+        let road = self.road.unwrap_or(road) as u64;
+        let field = self.field.unwrap_or(field) as u64;
+        let cloister = self.cloister.unwrap_or(cloister) as u64;
+        specs
+            .iter()
+            .map(|spec| {
+                let edges: u64 = spec
+                    .edge_features
+                    .iter()
+                    .map(|feature| match feature {
+                        Feature::City => city,
+                        Feature::Road => road,
+                        Feature::None => field,
+                        Feature::River => 100,
+                    })
+                    .fold(0, u64::saturating_add);
+                let tile = self.tiles.get(&spec.id).copied().unwrap_or(100) as u64;
+                let cloister = match spec.cloister {
+                    true => cloister,
+                    false => 100,
+                };
+                let scale = tile.saturating_mul(edges).saturating_mul(cloister);
+                let weight = (spec.count.max(0) as u64).saturating_mul(scale) / (400 * 100);
+                // A tile weighted at all is never rounded away
+                match weight {
+                    _ if scale == 0 || spec.count <= 0 => 0,
+                    _ => weight.clamp(1, MAX_TILE_WEIGHT as u64) as u32,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::base;
+
+    fn weight(weights: &[u32], specs: &[TileSpec], id: &str) -> u32 {
+        weights[specs.iter().position(|spec| spec.id == id).unwrap()]
+    }
+
+    #[test]
+    fn balanced_weights_follow_the_box() {
+        let specs = base();
+        let weights = Weights::default().tile_weights(&specs);
+        for (spec, weight) in specs.iter().zip(weights) {
+            assert_eq!(weight, spec.count as u32 * 100);
+        }
+    }
+
+    #[test]
+    fn presets_shift_the_mix() {
+        let specs = base();
+        let dense = Weights {
+            preset: Preset::DenseCities,
+            ..Weights::default()
+        }
+        .tile_weights(&specs);
+        assert!(weight(&dense, &specs, "citynesw_shield") > 100);
+        assert!(weight(&dense, &specs, "cloister") < 400);
+        let monastic = Weights {
+            preset: Preset::Monastic,
+            ..Weights::default()
+        }
+        .tile_weights(&specs);
+        assert!(weight(&monastic, &specs, "cloister") > 400);
+    }
+
+    #[test]
+    fn tiles_are_weighted_on_top_of_features() {
+        let specs = base();
+        let weights = Weights {
+            city: Some(200),
+            tiles: HashMap::from([("roadns".to_string(), 0), ("cityn".to_string(), 50)]),
+            ..Weights::default()
+        }
+        .tile_weights(&specs);
+        assert_eq!(weight(&weights, &specs, "roadns"), 0);
+        // One city edge of four at double weight, then halved
+        assert_eq!(weight(&weights, &specs, "cityn"), 5 * 100 * 5 / 4 / 2);
+        assert_eq!(weight(&weights, &specs, "citynesw_shield"), 200);
+    }
+
+    #[test]
+    fn unknown_tiles_are_rejected() {
+        let specs = base();
+        let weights = Weights {
+            tiles: HashMap::from([("zebra".to_string(), 1), ("castle".to_string(), 1)]),
+            ..Weights::default()
+        };
+        assert_eq!(
+            weights.check(&specs),
+            Err(GenerateError::UnknownTile {
+                id: "castle".to_string()
+            })
+        );
+        assert_eq!(Weights::default().check(&specs), Ok(()));
+    }
+
+    #[test]
+    fn huge_weights_are_held_to_the_limit() {
+        let specs = base();
+        let weights = Weights {
+            city: Some(u32::MAX),
+            cloister: Some(u32::MAX),
+            tiles: HashMap::from([("cloister".to_string(), u32::MAX)]),
+            ..Weights::default()
+        }
+        .tile_weights(&specs);
+        assert_eq!(weight(&weights, &specs, "cloister"), MAX_TILE_WEIGHT);
+        assert_eq!(weight(&weights, &specs, "citynesw_shield"), MAX_TILE_WEIGHT);
+        assert_eq!(weight(&weights, &specs, "roadns"), 800);
+    }
+
+    #[test]
+    fn weighting_every_tile_0_is_rejected() {
+        let specs = base();
+        let weights = Weights {
+            city: Some(0),
+            road: Some(0),
+            field: Some(0),
+            cloister: Some(0),
+            ..Weights::default()
+        };
+        assert_eq!(weights.check(&specs), Err(GenerateError::NoTileWeighted));
+        let one = Weights {
+            tiles: specs
+                .iter()
+                .filter(|spec| spec.id != "cloister")
+                .map(|spec| (spec.id.clone(), 0))
+                .collect(),
+            ..Weights::default()
+        };
+        assert_eq!(one.check(&specs), Ok(()));
+    }
+
+    #[test]
+    fn parses_presets() {
+        assert_eq!("road-network".parse(), Ok(Preset::RoadNetwork));
+        assert!("urban".parse::<Preset>().is_err());
+    }
+}
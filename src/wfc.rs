@@ -0,0 +1,345 @@
+//! Wave function collapse over the tiles in the deck.
+//!
+//! Every empty cell starts with each tile and rotation which fits the tiles
+//! already placed around it. The cell with the fewest states left is
+//! collapsed to one of them, chosen by its weight in the deck, and the states
+//! its neighbours can no longer match are removed, spreading outwards until
+//! nothing else changes. When the last of a tile is drawn from a finite deck
+//! its states are removed from every cell in the same way.
+
+use std::collections::VecDeque;
+
+use crate::deck::Deck;
+use crate::error::GenerateError;
+use crate::map::{Map, PlacedTile};
+use crate::rng::Rng;
+use crate::solver::{self, Candidate};
+use crate::tile::Feature;
+
+/// Number of times to start again after reaching a cell with no states left.
+const MAX_ATTEMPTS: usize = 100;
+
+/// Fills `cells` using wave function collapse, tiles already on the map are
+/// kept.
+pub fn fill(
+    map: &mut Map,
+    candidates: &[Candidate],
+    deck: &mut Deck,
+    cells: &[(usize, usize)],
+    rng: &mut Rng,
+) -> Result<(), GenerateError> {
+    if let Some((x, y)) = solver::first_unfillable(map, candidates, cells) {
+        return Err(GenerateError::NoTileFits { x, y });
+    }
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        if attempt > 1 {
+            for &(x, y) in cells {
+                solver::clear(map, deck, x, y);
+            }
+        }
+        if Wave::new(map, candidates, deck, cells).collapse(map, deck, rng) {
+            log::debug!("Collapsed {} cells in {} attempts", cells.len(), attempt);
+            return Ok(());
+        }
+    }
+    Err(GenerateError::CollapseFailed {
+        attempts: MAX_ATTEMPTS,
+    })
+}
+
+/// Bit for each feature, so sets of features can be compared cheaply.
+fn feature_bit(feature: Feature) -> u8 {
+    match feature {
+        Feature::None => 1,
+        Feature::City => 2,
+        Feature::Road => 4,
+        Feature::River => 8,
+    }
+}
+
+struct Wave<'a> {
+    candidates: &'a [Candidate],
+    /// Edge features of each candidate, north, east, south, west.
+    edges: Vec<[u8; 4]>,
+    size_x: usize,
+    /// States still possible for each cell, `None` for cells which are not
+    /// being filled or have been collapsed.
+    states: Vec<Option<Vec<bool>>>,
+    /// Number of states still possible for each cell.
+    counts: Vec<usize>,
+}
+
+impl<'a> Wave<'a> {
+    fn new(map: &Map, candidates: &'a [Candidate], deck: &Deck, cells: &[(usize, usize)]) -> Self {
+        let edges = candidates
+            .iter()
+            .map(|c| {
+                let spec = &map.specs()[c.tile as usize];
+                [0, 1, 2, 3].map(|i| feature_bit(spec.edge(i, c.rotation)))
+            })
+            .collect();
+        let size_x = map.size_x() as usize;
+        let mut states = vec![None; size_x * map.size_y() as usize];
+        let mut counts = vec![0; states.len()];
+        for &(x, y) in cells {
+            let possible: Vec<bool> = candidates
+                .iter()
+                .map(|c| deck.has(c.tile) && map.can_be_placed(c.tile, x, y, c.rotation))
+                .collect();
+            counts[y * size_x + x] = possible.iter().filter(|&&p| p).count();
+            states[y * size_x + x] = Some(possible);
+        }
+        Wave {
+            candidates,
+            edges,
+            size_x,
+            states,
+            counts,
+        }
+    }
+
+    /// Collapses every cell, returning false if a cell is left with no
+    /// states.
+    fn collapse(mut self, map: &mut Map, deck: &mut Deck, rng: &mut Rng) -> bool {
+        while let Some(index) = self.lowest_entropy(rng) {
+            let possible = self.states[index].take().unwrap();
+            let mut options: Vec<Candidate> = self
+                .candidates
+                .iter()
+                .zip(&possible)
+                .filter(|(_, &p)| p)
+                .map(|(c, _)| *c)
+                .collect();
+            if options.is_empty() {
+                return false;
+            }
+            let (x, y) = (index % self.size_x, index / self.size_x);
+            let chosen = solver::take_from_deck(&mut options, map, deck, x, y, rng);
+            let state = self.candidates.iter().position(|c| *c == chosen).unwrap();
+
+            map.tiles[y][x] = Some(PlacedTile {
+                tile_spec: chosen.tile,
+                rotation: chosen.rotation,
+            });
+            deck.take(chosen.tile);
+
+            let mut queue = VecDeque::from([(x, y, self.edges[state])]);
+            if !deck.has(chosen.tile) && !self.remove_tile(chosen.tile, &mut queue) {
+                return false;
+            }
+            if !self.propagate(map, queue) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Picks the cell with the fewest states left, breaking ties at random.
+    /// The number of states stands in for entropy so that results do not
+    /// depend on floating point logarithms.
+    fn lowest_entropy(&self, rng: &mut Rng) -> Option<usize> {
+        let mut best = None;
+        let mut best_count = usize::MAX;
+        let mut ties = 0;
+        for (index, possible) in self.states.iter().enumerate() {
+            if possible.is_none() {
+                continue;
+            }
+            let count = self.counts[index];
+            if count < best_count {
+                best = Some(index);
+                best_count = count;
+                ties = 1;
+            } else if count == best_count {
+                ties += 1;
+                if rng.below(ties) == 0 {
+                    best = Some(index);
+                }
+            }
+        }
+        best
+    }
+
+    /// Removes every state using `tile` once none are left in the deck,
+    /// queueing the cells which changed. Returns false if a cell is left with
+    /// no states.
+    fn remove_tile(&mut self, tile: u8, queue: &mut VecDeque<(usize, usize, [u8; 4])>) -> bool {
+        for index in 0..self.states.len() {
+            let Some(possible) = &mut self.states[index] else {
+                continue;
+            };
+            let mut changed = false;
+            for (state, p) in possible.iter_mut().enumerate() {
+                if *p && self.candidates[state].tile == tile {
+                    *p = false;
+                    self.counts[index] -= 1;
+                    changed = true;
+                }
+            }
+            if !changed {
+                continue;
+            }
+            let edges = self.allowed_edges(index);
+            if edges == [0; 4] {
+                return false;
+            }
+            queue.push_back((index % self.size_x, index / self.size_x, edges));
+        }
+        true
+    }
+
+    /// Returns the features each edge of a cell may still have.
+    fn allowed_edges(&self, index: usize) -> [u8; 4] {
+        let mut edges = [0; 4];
+        let Some(possible) = &self.states[index] else {
+            return edges;
+        };
+        for (state, _) in possible.iter().enumerate().filter(|(_, &p)| p) {
+            for (edge, state_edge) in edges.iter_mut().zip(self.edges[state]) {
+                *edge |= state_edge;
+            }
+        }
+        edges
+    }
+
+    /// Removes the states which no longer match their neighbours, starting
+    /// from the queued cells whose edges can now only have the given
+    /// features. Returns false if a cell is left with no states.
+    fn propagate(&mut self, map: &Map, mut queue: VecDeque<(usize, usize, [u8; 4])>) -> bool {
+        while let Some((x, y, edges)) = queue.pop_front() {
+            for i in 0..4 {
+                let Some((new_x, new_y)) = map.neighbour(x, y, i) else {
+                    continue;
+                };
+                let index = new_y * self.size_x + new_x;
+                let Some(possible) = &mut self.states[index] else {
+                    continue;
+                };
+
+                let mut changed = false;
+                let mut new_edges = [0; 4];
+                for (state, p) in possible.iter_mut().enumerate() {
+                    if !*p {
+                        continue;
+                    }
+                    let state_edges = self.edges[state];
+                    if state_edges[(i + 2) % 4] & edges[i] == 0 {
+                        *p = false;
+                        self.counts[index] -= 1;
+                        changed = true;
+                        continue;
+                    }
+                    for j in 0..4 {
+                        new_edges[j] |= state_edges[j];
+                    }
+                }
+                if new_edges == [0; 4] {
+                    return false;
+                }
+                if changed {
+                    queue.push_back((new_x, new_y, new_edges));
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::tests::{base, edges_match, tile};
+
+    /// Returns each tile as many times as it comes in the box.
+    fn deck(map: &Map) -> Vec<u8> {
+        map.specs()
+            .iter()
+            .enumerate()
+            .flat_map(|(tile, spec)| std::iter::repeat_n(tile as u8, spec.count as usize))
+            .collect()
+    }
+
+    fn cells(map: &Map) -> Vec<(usize, usize)> {
+        (0..map.size_y() as usize)
+            .flat_map(|y| (0..map.size_x() as usize).map(move |x| (x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn collapses_every_cell_into_matching_tiles() {
+        let mut map = Map::new(base(), 10, 7);
+        let candidates = solver::candidates(&map, &deck(&map));
+        let cells = cells(&map);
+        fill(
+            &mut map,
+            &candidates,
+            &mut Deck::unlimited(),
+            &cells,
+            &mut Rng::new(4),
+        )
+        .unwrap();
+        assert!(map.tiles.iter().flatten().all(Option::is_some));
+        assert!(edges_match(&map));
+    }
+
+    #[test]
+    fn keeps_the_tiles_already_placed() {
+        let mut map = Map::new(base(), 5, 5);
+        let cloister = map
+            .specs()
+            .iter()
+            .position(|spec| spec.id == "cloister")
+            .unwrap() as u8;
+        map.tiles[2][2] = Some(PlacedTile {
+            tile_spec: cloister,
+            rotation: 0,
+        });
+        let candidates = solver::candidates(&map, &deck(&map));
+        let cells: Vec<(usize, usize)> = cells(&map)
+            .into_iter()
+            .filter(|&cell| cell != (2, 2))
+            .collect();
+        fill(
+            &mut map,
+            &candidates,
+            &mut Deck::unlimited(),
+            &cells,
+            &mut Rng::new(5),
+        )
+        .unwrap();
+        assert_eq!(map.tile(2, 2).unwrap().tile_spec, cloister);
+        assert!(edges_match(&map));
+    }
+
+    #[test]
+    fn an_impossible_map_fails_to_collapse() {
+        // Each empty cell is forced to turn the tile so its road faces the
+        // city of the other
+        let spec = tile(
+            "cityroad",
+            [Feature::None, Feature::City, Feature::None, Feature::Road],
+        );
+        let mut map = Map::new(vec![spec], 4, 1);
+        for x in [0, 3] {
+            map.tiles[0][x] = Some(PlacedTile {
+                tile_spec: 0,
+                rotation: 0,
+            });
+        }
+        let candidates = solver::candidates(&map, &[0]);
+        let result = fill(
+            &mut map,
+            &candidates,
+            &mut Deck::unlimited(),
+            &[(1, 0), (2, 0)],
+            &mut Rng::new(0),
+        );
+        assert_eq!(
+            result,
+            Err(GenerateError::CollapseFailed {
+                attempts: MAX_ATTEMPTS
+            })
+        );
+    }
+}
@@ -34,7 +34,10 @@ import riversw from './river/riversw.png'
 import riversw_roadne from './river/riversw_roadne.png'
 import river_start from './river/river_start.png'
 
+import catalog from './tiles.json'
+
 export default {
+    'catalog': catalog,
     'base': {
         'cityew': cityew,
         'cityew_shield': cityew_shield,